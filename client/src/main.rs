@@ -3,7 +3,10 @@
 use {
     base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
     fluvio_wasm_timer::Delay,
-    futures::{stream, FutureExt, SinkExt, StreamExt, TryStreamExt},
+    futures::{
+        channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+        stream, FutureExt, SinkExt, StreamExt, TryStreamExt,
+    },
     js_sys::{Array, Reflect},
     leptos::{
         self, create_component, leptos_dom,
@@ -16,14 +19,22 @@ use {
         For, ForProps, IntoAttribute, IntoChild, NodeRef, Prop, ReadSignal, RwSignal, Scope,
         WriteSignal,
     },
-    once_cell::unsync::OnceCell,
     reqwasm::{
         http::Request,
         websocket::{futures::WebSocket, Message, WebSocketError},
     },
-    spin_webrtc_protocol::{ClientMessage, PeerMessage, ServerMessage},
+    spin_webrtc_protocol::{
+        ClientConfig, ClientMessage, PeerMessage, Resume, ServerMessage, PROTOCOL_VERSION,
+    },
     std::{
-        cell::RefCell, collections::HashMap, fmt::Debug, io, ops::Deref, rc::Rc, time::Duration,
+        borrow::Cow,
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        fmt::Debug,
+        io,
+        ops::Deref,
+        rc::Rc,
+        time::Duration,
     },
     thiserror::Error,
     wasm_bindgen::{closure::Closure, JsCast, JsValue},
@@ -32,6 +43,29 @@ use {
 
 const PING_INTERVAL: Duration = Duration::from_secs(30);
 
+/// Number of consecutive pings the server may leave unanswered before we consider it unreachable.
+const MAX_UNANSWERED_PINGS: u32 = 2;
+
+/// How long to wait before trying to reconnect after losing the websocket connection.  The delay doubles after
+/// each failed attempt, up to `MAX_RECONNECT_DELAY`, and resets once the server welcomes us again.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Optional protocol features this client supports; see `ServerMessage::Hello`.
+const FEATURES: &[&str] = &[
+    "chat",
+    "pong",
+    "kick",
+    "room_full",
+    "password",
+    "token",
+    "host",
+    "lobby",
+    "invite",
+    "members",
+];
+
 #[derive(Error, Debug)]
 pub enum MyError {
     #[error("JS error")]
@@ -52,17 +86,44 @@ pub enum MyError {
     #[error("HTTP error")]
     Http(#[from] reqwasm::Error),
 
-    #[error("redundant ClientMessage::You")]
-    RedundantYou,
-
     #[error("missed ClientMessage::You")]
     MissingYou,
 
+    #[error("server requires protocol version between {min_version} and {max_version}")]
+    Rejected { min_version: u32, max_version: u32 },
+
     #[error("unexpected message")]
     UnexpectedMessage(Message),
 
     #[error("not a string")]
     NotAString,
+
+    #[error("signaling channel closed")]
+    Disconnected,
+
+    #[error("server stopped answering pings")]
+    Unresponsive,
+
+    #[error("removed from room")]
+    Kicked,
+
+    #[error("room is full")]
+    RoomFull,
+
+    #[error("room is locked")]
+    RoomLocked,
+
+    #[error("host denied entry")]
+    Denied,
+
+    #[error("room requires a password")]
+    PasswordRequired,
+
+    #[error("room requires an invite")]
+    InviteRequired,
+
+    #[error("authentication required")]
+    Unauthenticated,
 }
 
 impl From<JsValue> for MyError {
@@ -113,6 +174,141 @@ impl ChatLog {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Status {
+    Connecting,
+    Connected,
+    Waiting,
+    Denied,
+    Reconnecting,
+    Disconnected,
+    Kicked,
+    RoomFull,
+    RoomLocked,
+    PasswordRequired,
+    InviteRequired,
+    Unauthenticated,
+    Outdated,
+}
+
+impl Status {
+    fn class(self) -> &'static str {
+        match self {
+            Status::Connecting => "notification is-info",
+            Status::Connected => "notification is-hidden",
+            Status::Waiting => "notification is-info",
+            Status::Denied => "notification is-danger",
+            Status::Reconnecting => "notification is-warning",
+            Status::Disconnected => "notification is-danger",
+            Status::Kicked => "notification is-danger",
+            Status::RoomFull => "notification is-warning",
+            Status::RoomLocked => "notification is-warning",
+            Status::PasswordRequired => "notification is-warning",
+            Status::InviteRequired => "notification is-warning",
+            Status::Unauthenticated => "notification is-warning",
+            Status::Outdated => "notification is-warning",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Status::Connecting => "Connecting to server...",
+            Status::Connected => "",
+            Status::Waiting => "Waiting for the host to let you in...",
+            Status::Denied => "Sorry, the host didn't let you in.",
+            Status::Reconnecting => "Lost connection to server; reconnecting...",
+            Status::Disconnected => "Lost connection to server",
+            Status::Kicked => "You have been removed from this room",
+            Status::Unauthenticated => "Please sign in to join this room.",
+            Status::Outdated => "This page is out of date.  Please reload it to rejoin.",
+            Status::PasswordRequired => {
+                "This room is password-protected.  Reload the page to try again."
+            }
+            Status::InviteRequired => {
+                "Sorry, this room is invite-only.  Please ask its host for an invite link."
+            }
+            Status::RoomFull => {
+                "Sorry, this room is full.  Please try again later, or start a new room."
+            }
+            Status::RoomLocked => {
+                "Sorry, this room is locked.  Please ask its host to unlock it, or start a new room."
+            }
+        }
+    }
+}
+
+/// Signals through which `connect` updates the UI.
+#[derive(Copy, Clone)]
+struct Ui {
+    local_video: WriteSignal<Option<MediaStream>>,
+    remote_videos: WriteSignal<Vec<(u64, Rc<str>, ReadSignal<MediaStream>)>>,
+    chat_log: WriteSignal<ChatLog>,
+    status: WriteSignal<Status>,
+    /// Whether we're the host of our room, and may thus moderate it.
+    is_host: WriteSignal<bool>,
+    locked: WriteSignal<bool>,
+    muted: WriteSignal<bool>,
+    /// Peers (and their users, if known) waiting in our room's lobby, if we're its host.
+    knocks: WriteSignal<Vec<(Rc<str>, Option<String>)>>,
+}
+
+/// Who the server says we are; see `ClientMessage::You`.
+struct Identity {
+    id: String,
+    secret: String,
+}
+
+/// Our end of the signaling channel to the server.
+///
+/// This outlives any one websocket connection: `connect` opens a new one whenever the old one is lost.
+struct Signaling {
+    room: String,
+    /// Password to present when joining `room`, or to protect it with if we're the first to join.
+    password: RefCell<Option<String>>,
+    /// Invite token to present when joining `room`.
+    invite: Option<String>,
+    /// Token from the server's identity provider to present when joining `room`.
+    token: Option<String>,
+    /// Where to send the user if the server wants a (new) token; see `ClientConfig::login_url`.
+    login_url: Option<String>,
+    me: RefCell<Option<Identity>>,
+    outbound: UnboundedSender<Message>,
+    /// Whether the server has welcomed us since we last (re)connected.
+    welcomed: Cell<bool>,
+    /// Whether the server negotiated the "pong" feature and will thus answer our pings.
+    expect_pongs: Cell<bool>,
+    unanswered_pings: Cell<u32>,
+}
+
+impl Signaling {
+    /// Queue `message` for delivery to the server once the websocket is able to send it.
+    fn send(&self, message: &ServerMessage) -> Result<(), MyError> {
+        self.outbound
+            .unbounded_send(Message::Text(serde_json::to_string(message)?))
+            .map_err(|_| MyError::Disconnected)
+    }
+
+    /// Ask the server to add us to our room.
+    fn join_room(&self) -> Result<(), MyError> {
+        self.send(&ServerMessage::Room {
+            name: &self.room,
+            password: self.password.borrow().as_deref().map(Cow::Borrowed),
+            invite: self.invite.as_deref().map(Cow::Borrowed),
+            token: self.token.as_deref().map(Cow::Borrowed),
+        })
+    }
+
+    /// Ask the server to relay `message` to the peer identified by `to`.
+    fn send_to_peer(&self, to: &str, message: PeerMessage) -> Result<(), MyError> {
+        // We aren't in a room (and thus have no peers) until the server has told us who we are.
+        if self.me.borrow().is_none() {
+            return Err(MyError::MissingYou);
+        }
+
+        self.send(&ServerMessage::Signal { to, message })
+    }
+}
+
 struct Connection {
     id: u64,
     connection: RtcPeerConnection,
@@ -126,8 +322,8 @@ fn main() {
 
     let location = web_sys::window().unwrap().location();
 
-    // If a room has not yet been specified, generate a random ID and use that as the room name.
-    if !location.pathname().unwrap().starts_with("/room") {
+    // If a room has not yet been specified, generate a random ID and use that.
+    if room_id().is_none() {
         let mut bytes = [0u8; 16];
         getrandom::getrandom(&mut bytes).unwrap();
 
@@ -136,26 +332,45 @@ fn main() {
         location.set_pathname(&format!("/room/{room}")).unwrap()
     }
 
-    leptos::mount_to_body(videos);
+    wasm_bindgen_futures::spawn_local(async {
+        match fetch_config().await {
+            Ok(config) => {
+                let config = Rc::new(config);
+
+                leptos::mount_to_body(move |cx| videos(cx, config.clone()));
+            }
+
+            Err(e) => log::error!("fatal error: unable to fetch config: {e:?}"),
+        }
+    });
 }
 
-async fn send_to_peer(
-    me: &OnceCell<Box<str>>,
-    url: &str,
-    message: PeerMessage<'_>,
-) -> Result<(), MyError> {
-    Request::post(url)
-        .body(serde_json::to_string(&ClientMessage::Peer {
-            url: me.get().ok_or(MyError::MissingYou)?,
-            message,
-        })?)
-        .send()
-        .await
-        .map_err(MyError::from)
-        .map(drop)
+/// Get the ID of the room named by the current page's path (i.e. `/room/{id}`), if any.
+fn room_id() -> Option<String> {
+    let pathname = web_sys::window().unwrap().location().pathname().ok()?;
+    let id = pathname.strip_prefix("/room/")?.split('/').next()?;
+
+    (!id.is_empty()).then(|| id.to_owned())
 }
 
-fn videos(cx: Scope) -> Element {
+/// Get the value of the specified parameter from a URL query string or fragment (e.g. `?a=1&b=2` or `#a=1`).
+///
+/// Room passwords are passed in the fragment, which browsers never send to the server.
+fn url_parameter(params: &str, name: &str) -> Option<String> {
+    params
+        .trim_start_matches(['?', '#'])
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        .and_then(|value| js_sys::decode_uri_component(value).ok())
+        .map(String::from)
+        .filter(|value| !value.is_empty())
+}
+
+async fn fetch_config() -> Result<ClientConfig, MyError> {
+    Ok(Request::get("/config").send().await?.json().await?)
+}
+
+fn videos(cx: Scope, config: Rc<ClientConfig>) -> Element {
     let (local_video, set_local_video) = leptos::create_signal(cx, None);
 
     let (remote_videos, set_remote_videos) = leptos::create_signal(cx, Vec::new());
@@ -171,38 +386,172 @@ fn videos(cx: Scope) -> Element {
         },
     );
 
-    let me = Rc::new(OnceCell::<Box<str>>::new());
+    let (status, set_status) = leptos::create_signal(cx, Status::Connecting);
+
+    let (is_host, set_is_host) = leptos::create_signal(cx, false);
+
+    let (locked, set_locked) = leptos::create_signal(cx, false);
+
+    let (muted, set_muted) = leptos::create_signal(cx, false);
+
+    let (knocks, set_knocks) = leptos::create_signal(cx, Vec::new());
+
+    // Muting just disables our audio tracks, so our peers need not renegotiate anything.
+    leptos::create_effect(cx, move |_| {
+        let muted = muted.get();
+
+        if let Some(stream) = local_video.get() {
+            for track in stream.get_audio_tracks().iter() {
+                if let Ok(track) = track.dyn_into::<MediaStreamTrack>() {
+                    track.set_enabled(!muted);
+                }
+            }
+        }
+    });
+
+    let (outbound, outbound_rx) = mpsc::unbounded();
+
+    let location = web_sys::window().unwrap().location();
+
+    let signaling = Rc::new(Signaling {
+        room: room_id().unwrap_or_default(),
+        password: RefCell::new(url_parameter(
+            &location.hash().unwrap_or_default(),
+            "password",
+        )),
+        invite: url_parameter(&location.search().unwrap_or_default(), "invite"),
+        // Identity providers using the OpenID Connect implicit flow return `id_token` in the fragment.
+        token: url_parameter(&location.hash().unwrap_or_default(), "id_token")
+            .or_else(|| url_parameter(&location.hash().unwrap_or_default(), "token")),
+        login_url: config.login_url.clone(),
+        me: RefCell::new(None),
+        outbound,
+        welcomed: Cell::new(false),
+        expect_pongs: Cell::new(false),
+        unanswered_pings: Cell::new(0),
+    });
+
+    // Now that we've read them, drop the credentials in the fragment (e.g. tokens from the identity provider)
+    // from the address bar, and thus from the browser's history and any links copied from it.
+    if !location.hash().unwrap_or_default().is_empty() {
+        let stripped = location
+            .pathname()
+            .and_then(|pathname| Ok(format!("{pathname}{}", location.search()?)))
+            .and_then(|url| {
+                web_sys::window()
+                    .unwrap()
+                    .history()?
+                    .replace_state_with_url(&JsValue::NULL, "", Some(&url))
+            });
+
+        if let Err(e) = stripped {
+            log::warn!("error removing fragment from URL: {e:?}");
+        }
+    }
 
     let connections = Rc::new(RefCell::new(HashMap::<Rc<str>, Connection>::new()));
 
+    let chat_class = if config.features.iter().any(|feature| feature == "chat") {
+        "column is-one-fifth-desktop is-full-touch"
+    } else {
+        "column is-one-fifth-desktop is-full-touch is-hidden"
+    };
+
     wasm_bindgen_futures::spawn_local({
-        let me = me.clone();
+        let signaling = signaling.clone();
         let connections = connections.clone();
 
+        let ui = Ui {
+            local_video: set_local_video,
+            remote_videos: set_remote_videos,
+            chat_log: set_chat_log,
+            status: set_status,
+            is_host: set_is_host,
+            locked: set_locked,
+            muted: set_muted,
+            knocks: set_knocks,
+        };
+
         async move {
-            if let Err(e) = connect(
-                cx,
-                me,
-                connections,
-                set_local_video,
-                set_remote_videos,
-                set_chat_log,
-            )
-            .await
-            {
+            if let Err(e) = connect(cx, config, signaling, outbound_rx, connections, ui).await {
                 log::error!("fatal error: {e:?}");
+
+                set_status.set(match e {
+                    // The server no longer speaks our protocol, most likely because this copy of the client was
+                    // cached from before an upgrade; reloading fetches the current one.
+                    MyError::Rejected { .. } => Status::Outdated,
+                    MyError::Kicked => Status::Kicked,
+                    MyError::RoomFull => Status::RoomFull,
+                    MyError::RoomLocked => Status::RoomLocked,
+                    MyError::Denied => Status::Denied,
+                    MyError::PasswordRequired => Status::PasswordRequired,
+                    MyError::InviteRequired => Status::InviteRequired,
+                    MyError::Unauthenticated => Status::Unauthenticated,
+                    _ => Status::Disconnected,
+                });
             }
         }
     });
 
-    let on_key = make_key_listener(connections, me, set_chat_log);
+    let on_key = make_key_listener(connections, signaling.clone(), set_chat_log);
+
+    let on_video_click = make_video_click_listener(signaling.clone(), is_host);
+
+    let on_knock_click = make_knock_click_listener(signaling.clone());
+
+    let on_toggle_mute = move |_: web_sys::Event| set_muted.update(|muted| *muted = !*muted);
+
+    let on_mute_all = {
+        let signaling = signaling.clone();
+
+        move |_: web_sys::Event| {
+            if let Err(e) = signaling.send(&ServerMessage::MuteAll) {
+                log::warn!("error asking room to mute: {e:?}");
+            }
+        }
+    };
+
+    let on_lock = move |_: web_sys::Event| {
+        if let Err(e) = signaling.send(&ServerMessage::Lock {
+            locked: !locked.get(),
+        }) {
+            log::warn!("error locking room: {e:?}");
+        }
+    };
+
+    let host_button_class = move || {
+        if is_host.get() {
+            "button"
+        } else {
+            "button is-hidden"
+        }
+    };
 
     leptos::view! { cx,
         <div id="parent" class="columns is-multiline" class="column is-four-fifths-desktop is-full-touch">
             <div id="videos" class="column is-four-fifths-desktop is-full-touch">
-                <div class="tile is-ancestor is-multiline">
+                <div id="status" class=move || status.get().class()>
+                    {move || status.get().message().to_owned()}
+                </div>
+                <div id="controls" class="buttons">
+                    <button class="button" on:click=on_toggle_mute>
+                        {move || if muted.get() { "Unmute" } else { "Mute" }.to_owned()}
+                    </button>
+                    <button class=host_button_class on:click=on_mute_all>
+                        "Mute everyone"
+                    </button>
+                    <button class=host_button_class on:click=on_lock>
+                        {move || if locked.get() { "Unlock room" } else { "Lock room" }.to_owned()}
+                    </button>
+                </div>
+                <div id="knocks" on:click=on_knock_click>
+                    <For each=move || knocks.get() key=|(id, _)| id.clone()>
+                        {knock_element}
+                    </For>
+                </div>
+                <div class="tile is-ancestor is-multiline" on:click=on_video_click>
                     {local_video_element(cx, local_video)}
-                    <For each=move || remote_videos.get() key=|(id, _)| *id>
+                    <For each=move || remote_videos.get() key=|(id, _, _)| *id>
                         {remote_video_element}
                     </For>
                 </div>
@@ -250,7 +599,7 @@ fn videos(cx: Scope) -> Element {
                     </footer>
                 </div>
             </div>
-            <div id="chat" id="chat" class="column is-one-fifth-desktop is-full-touch">
+            <div id="chat" id="chat" class=chat_class>
                 <div id="chatLog" _ref=chat_log_ref>
                     <h2>"Chat"</h2>
                     <For each=move || chat_log.get().log key=|(id, _)| *id>
@@ -280,11 +629,17 @@ fn local_video_element(cx: Scope, local_video: ReadSignal<Option<MediaStream>>)
     element.into()
 }
 
-fn remote_video_element(cx: Scope, (_, video): &(u64, ReadSignal<MediaStream>)) -> Element {
+fn remote_video_element(
+    cx: Scope,
+    (_, peer, video): &(u64, Rc<str>, ReadSignal<MediaStream>),
+) -> Element {
     let element = leptos::view! { _, <video class="tile is-child is-6" playsinline autoplay/> }
         .dyn_into::<HtmlVideoElement>()
         .unwrap();
 
+    // See `make_video_click_listener`.
+    element.set_attribute("data-peer", peer).unwrap();
+
     leptos::create_effect(cx, {
         let element = element.clone();
         let video = *video;
@@ -297,6 +652,24 @@ fn remote_video_element(cx: Scope, (_, video): &(u64, ReadSignal<MediaStream>))
     element.into()
 }
 
+fn knock_element(cx: Scope, (id, user): &(Rc<str>, Option<String>)) -> Element {
+    let who = user.clone().unwrap_or_else(|| "Someone".to_owned());
+
+    let element = leptos::view! { cx,
+        <div class="notification is-info">
+            <b>{who}</b>" would like to join.  "
+            <button class="button is-small admit">"Admit"</button>
+            " "
+            <button class="button is-small deny">"Deny"</button>
+        </div>
+    };
+
+    // See `make_knock_click_listener`.
+    element.set_attribute("data-peer", id).unwrap();
+
+    element
+}
+
 fn chat_log_element(cx: Scope, (_, message): &(u64, ChatMessage)) -> Element {
     let (who, class) = match message.source {
         ChatSource::Me => ("me: ", "sent"),
@@ -308,7 +681,7 @@ fn chat_log_element(cx: Scope, (_, message): &(u64, ChatMessage)) -> Element {
 
 fn make_key_listener(
     connections: Rc<RefCell<HashMap<Rc<str>, Connection>>>,
-    me: Rc<OnceCell<Box<str>>>,
+    signaling: Rc<Signaling>,
     chat_log: WriteSignal<ChatLog>,
 ) -> impl Fn(KeyboardEvent) {
     move |event: KeyboardEvent| {
@@ -321,20 +694,15 @@ fn make_key_listener(
                 target.set_value("");
 
                 if !message.trim().is_empty() {
-                    for url in connections.borrow().keys() {
-                        wasm_bindgen_futures::spawn_local({
-                            let me = me.clone();
-                            let url = url.clone();
-                            let message = message.clone();
-
-                            async move {
-                                if let Err(e) =
-                                    send_to_peer(&me, &url, PeerMessage::Chat { message }).await
-                                {
-                                    log::warn!("error sending chat to {url}: {e:?}");
-                                }
-                            }
-                        });
+                    for peer in connections.borrow().keys() {
+                        if let Err(e) = signaling.send_to_peer(
+                            peer,
+                            PeerMessage::Chat {
+                                message: message.clone(),
+                            },
+                        ) {
+                            log::warn!("error sending chat to {peer}: {e:?}");
+                        }
                     }
 
                     chat_log.update(|log| {
@@ -349,43 +717,113 @@ fn make_key_listener(
     }
 }
 
+/// Let the host kick, or hand over the host role to, whoever's video it clicks on.
+fn make_video_click_listener(
+    signaling: Rc<Signaling>,
+    is_host: ReadSignal<bool>,
+) -> impl Fn(web_sys::Event) {
+    move |event| {
+        if !is_host.get() {
+            return;
+        }
+
+        let Some(peer) = event
+            .target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+            .and_then(|element| element.get_attribute("data-peer"))
+        else {
+            return;
+        };
+
+        let window = web_sys::window().unwrap();
+        let confirm = |message| window.confirm_with_message(message).unwrap_or(false);
+
+        let command = if confirm("Remove this person from the room?") {
+            ServerMessage::Kick { id: &peer }
+        } else if confirm("Make this person the host instead of you?") {
+            ServerMessage::TransferHost { to: &peer }
+        } else {
+            return;
+        };
+
+        if let Err(e) = signaling.send(&command) {
+            log::warn!("error moderating {peer}: {e:?}");
+        }
+    }
+}
+
+/// Admit or deny whoever's knock the host clicked a button for.
+fn make_knock_click_listener(signaling: Rc<Signaling>) -> impl Fn(web_sys::Event) {
+    move |event| {
+        let Some(button) = event
+            .target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+        else {
+            return;
+        };
+
+        let Some(peer) = button
+            .closest("[data-peer]")
+            .ok()
+            .flatten()
+            .and_then(|element| element.get_attribute("data-peer"))
+        else {
+            return;
+        };
+
+        let class = button.class_name();
+
+        let command = if class.split_whitespace().any(|class| class == "admit") {
+            ServerMessage::Admit { id: &peer }
+        } else if class.split_whitespace().any(|class| class == "deny") {
+            ServerMessage::Deny { id: &peer }
+        } else {
+            return;
+        };
+
+        // The knock stays listed until the server says the knocker has left the lobby, since admitting it has no
+        // effect if the room is full.
+        if let Err(e) = signaling.send(&command) {
+            log::warn!("error answering knock from {peer}: {e:?}");
+        }
+    }
+}
+
 fn ice_server(spec: &str) -> RtcIceServer {
     let mut server = RtcIceServer::new();
     server.urls(&JsValue::from_str(spec));
     server
 }
 
-fn rtc_config() -> RtcConfiguration {
+fn rtc_config(ice_servers: &[String]) -> RtcConfiguration {
     let mut config = RtcConfiguration::new();
     config.ice_servers(
-        [
-            ice_server("stun:stun.services.mozilla.com"),
-            ice_server("stun:stun.l.google.com:19302"),
-        ]
-        .into_iter()
-        .collect::<Array>()
-        .deref(),
+        ice_servers
+            .iter()
+            .map(|spec| ice_server(spec))
+            .collect::<Array>()
+            .deref(),
     );
     config
 }
 
 fn make_remote_video_updater(
     connections: Rc<RefCell<HashMap<Rc<str>, Connection>>>,
-    remote_videos: WriteSignal<Vec<(u64, ReadSignal<MediaStream>)>>,
+    remote_videos: WriteSignal<Vec<(u64, Rc<str>, ReadSignal<MediaStream>)>>,
 ) -> impl Fn() + Clone {
     move || {
         let mut vec = connections
             .borrow()
-            .values()
-            .filter_map(|connection| {
+            .iter()
+            .filter_map(|(peer, connection)| {
                 connection
                     .stream
                     .as_ref()
-                    .map(|stream| (connection.id, stream.read_only()))
+                    .map(|stream| (connection.id, peer.clone(), stream.read_only()))
             })
             .collect::<Vec<_>>();
 
-        vec.sort_by_key(|(id, _)| *id);
+        vec.sort_by_key(|(id, _, _)| *id);
 
         remote_videos.set(vec);
     }
@@ -394,18 +832,18 @@ fn make_remote_video_updater(
 fn make_track_listener(
     cx: Scope,
     connections: Rc<RefCell<HashMap<Rc<str>, Connection>>>,
-    remote_videos: WriteSignal<Vec<(u64, ReadSignal<MediaStream>)>>,
-    url: Rc<str>,
+    remote_videos: WriteSignal<Vec<(u64, Rc<str>, ReadSignal<MediaStream>)>>,
+    peer: Rc<str>,
 ) -> impl Fn(RtcTrackEvent) {
     let update_remote_videos = make_remote_video_updater(connections.clone(), remote_videos);
 
     move |event| match event.streams().at(0).dyn_into::<MediaStream>() {
         Ok(new_stream) => {
-            log::info!("got remote stream from {url}");
+            log::info!("got remote stream from {peer}");
 
             let mut need_update = false;
 
-            if let Some(connection) = connections.borrow_mut().get_mut(&url) {
+            if let Some(connection) = connections.borrow_mut().get_mut(&peer) {
                 if let Some(stream) = connection.stream {
                     stream.set(new_stream);
                 } else {
@@ -419,36 +857,26 @@ fn make_track_listener(
             }
         }
 
-        Err(e) => log::warn!("error getting stream from track for {url}: {e:?}"),
+        Err(e) => log::warn!("error getting stream from track for {peer}: {e:?}"),
     }
 }
 
 fn make_ice_listener(
-    me: Rc<OnceCell<Box<str>>>,
-    url: Rc<str>,
+    signaling: Rc<Signaling>,
+    peer: Rc<str>,
 ) -> impl Fn(RtcPeerConnectionIceEvent) {
     move |event| {
         if let Some(candidate) = event.candidate() {
-            let me = me.clone();
-            let url = url.clone();
-
-            wasm_bindgen_futures::spawn_local(async move {
-                send_to_peer(
-                    &me,
-                    &url,
-                    PeerMessage::Candidate {
-                        candidate: &candidate.candidate(),
-                        sdp_mid: candidate.sdp_mid().as_deref(),
-                        sdp_m_line_index: candidate.sdp_m_line_index(),
-                    },
-                )
-                .map(|result| {
-                    if let Err(e) = result {
-                        log::warn!("error sending ICE candidate to {url}: {e:?}");
-                    }
-                })
-                .await
-            })
+            if let Err(e) = signaling.send_to_peer(
+                &peer,
+                PeerMessage::Candidate {
+                    candidate: &candidate.candidate(),
+                    sdp_mid: candidate.sdp_mid().as_deref(),
+                    sdp_m_line_index: candidate.sdp_m_line_index(),
+                },
+            ) {
+                log::warn!("error sending ICE candidate to {peer}: {e:?}");
+            }
         }
     }
 }
@@ -456,23 +884,24 @@ fn make_ice_listener(
 fn make_connection_adder(
     cx: Scope,
     connections: Rc<RefCell<HashMap<Rc<str>, Connection>>>,
-    me: Rc<OnceCell<Box<str>>>,
-    remote_videos: WriteSignal<Vec<(u64, ReadSignal<MediaStream>)>>,
+    signaling: Rc<Signaling>,
+    remote_videos: WriteSignal<Vec<(u64, Rc<str>, ReadSignal<MediaStream>)>>,
     local_stream: MediaStream,
+    ice_servers: &[String],
 ) -> impl FnMut(&str) -> Result<RtcPeerConnection, MyError> {
     let update_remote_videos = make_remote_video_updater(connections.clone(), remote_videos);
-    let config = rtc_config();
+    let config = rtc_config(ice_servers);
     let mut next_id = 0;
 
-    move |url| {
-        log::info!("adding peer {url}");
+    move |peer| {
+        log::info!("adding peer {peer}");
 
-        let url = Rc::<str>::from(url);
+        let peer = Rc::<str>::from(peer);
 
         let connection = RtcPeerConnection::new_with_configuration(&config)?;
 
         connections.borrow_mut().insert(
-            url.clone(),
+            peer.clone(),
             Connection {
                 id: next_id,
                 connection: connection.clone(),
@@ -488,22 +917,23 @@ fn make_connection_adder(
             cx,
             connections.clone(),
             remote_videos,
-            url.clone(),
+            peer.clone(),
         )) as Box<dyn Fn(RtcTrackEvent)>);
 
         connection.set_ontrack(Some(ontrack.as_ref().unchecked_ref()));
 
         ontrack.forget();
 
-        let onicecandidate = Closure::wrap(Box::new(make_ice_listener(me.clone(), url.clone()))
-            as Box<dyn Fn(RtcPeerConnectionIceEvent)>);
+        let onicecandidate =
+            Closure::wrap(Box::new(make_ice_listener(signaling.clone(), peer.clone()))
+                as Box<dyn Fn(RtcPeerConnectionIceEvent)>);
 
         connection.set_onicecandidate(Some(onicecandidate.as_ref().unchecked_ref()));
 
         onicecandidate.forget();
 
         for track in local_stream.get_tracks().iter() {
-            log::info!("adding track for {url}: {track:?}");
+            log::info!("adding track for {peer}: {track:?}");
 
             connection.add_track(
                 &track.dyn_into::<MediaStreamTrack>()?,
@@ -523,9 +953,9 @@ fn get_sdp(object: &JsValue) -> Result<String, MyError> {
 }
 
 async fn handle_peer_message(
-    me: &OnceCell<Box<str>>,
+    signaling: &Signaling,
     chat_log: WriteSignal<ChatLog>,
-    url: &str,
+    peer: &str,
     connection: RtcPeerConnection,
     message: PeerMessage<'_>,
 ) -> Result<(), MyError> {
@@ -543,7 +973,7 @@ async fn handle_peer_message(
             ))
             .await?;
 
-            send_to_peer(me, url, PeerMessage::Answer { sdp }).await?;
+            signaling.send_to_peer(peer, PeerMessage::Answer { sdp })?;
         }
 
         PeerMessage::Answer { sdp } => {
@@ -582,9 +1012,9 @@ async fn handle_peer_message(
 }
 
 async fn handle_message(
+    signaling: &Signaling,
     connections: &RefCell<HashMap<Rc<str>, Connection>>,
-    me: &OnceCell<Box<str>>,
-    chat_log: WriteSignal<ChatLog>,
+    ui: Ui,
     add_connection: &mut dyn (FnMut(&str) -> Result<RtcPeerConnection, MyError>),
     update_remote_videos: &dyn (Fn()),
     message: Message,
@@ -593,14 +1023,123 @@ async fn handle_message(
 
     match message {
         Message::Text(message) => match serde_json::from_str::<ClientMessage>(&message)? {
-            ClientMessage::You { url } => {
-                me.set(Box::from(url)).map_err(|_| MyError::RedundantYou)?
+            ClientMessage::Welcome { version, features } => {
+                log::info!("server speaks protocol version {version} with features {features:?}");
+
+                signaling
+                    .expect_pongs
+                    .set(features.iter().any(|feature| feature == "pong"));
+
+                signaling.welcomed.set(true);
+
+                ui.status.set(Status::Connected);
+
+                signaling.join_room()?;
+            }
+
+            ClientMessage::Rejected {
+                min_version,
+                max_version,
+            } => {
+                return Err(MyError::Rejected {
+                    min_version,
+                    max_version,
+                })
+            }
+
+            ClientMessage::Pong => signaling.unanswered_pings.set(0),
+
+            ClientMessage::Kicked => return Err(MyError::Kicked),
+
+            ClientMessage::RoomFull => return Err(MyError::RoomFull),
+
+            ClientMessage::RoomLocked => return Err(MyError::RoomLocked),
+
+            ClientMessage::Denied => return Err(MyError::Denied),
+
+            ClientMessage::Waiting => ui.status.set(Status::Waiting),
+
+            ClientMessage::Knock { id, user } => ui.knocks.update(|knocks| {
+                if !knocks.iter().any(|(knocker, _)| **knocker == *id) {
+                    knocks.push((id.into(), user.map(Cow::into_owned)));
+                }
+            }),
+
+            ClientMessage::Host { id } => {
+                let is_host = matches!(signaling.me.borrow().as_ref(), Some(me) if me.id == id);
+
+                log::info!("room host is {id}{}", if is_host { " (us)" } else { "" });
+
+                // We only hear about the host once we're in the room, e.g. after waiting in its lobby.
+                ui.status.set(Status::Connected);
+
+                ui.is_host.set(is_host);
+
+                if !is_host {
+                    ui.knocks.set(Vec::new());
+                }
+            }
+
+            ClientMessage::Locked { locked } => ui.locked.set(locked),
+
+            ClientMessage::Mute => {
+                log::info!("host asked us to mute");
+
+                ui.muted.set(true);
+            }
+
+            ClientMessage::Unauthenticated => {
+                if let Some(login_url) = &signaling.login_url {
+                    web_sys::window().unwrap().location().set_href(login_url)?;
+                }
+
+                return Err(MyError::Unauthenticated);
+            }
+
+            ClientMessage::InviteRequired => return Err(MyError::InviteRequired),
+
+            ClientMessage::PasswordRequired => {
+                let password = web_sys::window().unwrap().prompt_with_message(
+                    "This room is password-protected.  Please enter the password:",
+                )?;
+
+                match password {
+                    Some(password) if !password.is_empty() => {
+                        *signaling.password.borrow_mut() = Some(password);
+
+                        signaling.join_room()?;
+                    }
+
+                    _ => return Err(MyError::PasswordRequired),
+                }
             }
 
-            ClientMessage::Add { url } => {
-                if !connections.borrow().contains_key(url) {
+            ClientMessage::You { id, secret } => {
+                let mut me = signaling.me.borrow_mut();
+
+                if matches!(me.as_ref(), Some(old) if old.id != id) {
+                    // The server didn't let us resume our old identity (e.g. because it evicted us while we were
+                    // disconnected), so our peers have already hung up on us.  They'll call us back under our new
+                    // identity once we rejoin.
+                    log::info!("server assigned new ID {id}; dropping existing connections");
+
+                    for (_, connection) in connections.borrow_mut().drain() {
+                        connection.connection.close();
+                    }
+
+                    update_remote_videos();
+                }
+
+                *me = Some(Identity {
+                    id: id.to_owned(),
+                    secret: secret.to_owned(),
+                });
+            }
+
+            ClientMessage::Add { id } => {
+                if !connections.borrow().contains_key(id) {
                     async {
-                        let connection = add_connection(url)?;
+                        let connection = add_connection(id)?;
 
                         let sdp = get_sdp(&JsFuture::from(connection.create_offer()).await?)?;
 
@@ -609,39 +1148,62 @@ async fn handle_message(
                         ))
                         .await?;
 
-                        send_to_peer(me, url, PeerMessage::Offer { sdp }).await
+                        signaling.send_to_peer(id, PeerMessage::Offer { sdp })
                     }
                     .map(|result| {
                         if let Err(e) = result {
-                            log::warn!("error adding connection {url}: {e:?}");
+                            log::warn!("error adding connection {id}: {e:?}");
                         }
                     })
                     .await
                 }
             }
 
-            ClientMessage::Remove { url } => {
-                connections.borrow_mut().remove(url);
+            ClientMessage::Members { ids } => {
+                // We've resumed after reconnecting, so hang up on anyone who left while we were away.
+                connections.borrow_mut().retain(|peer, connection| {
+                    let present = ids.contains(&&**peer);
+
+                    if !present {
+                        connection.connection.close();
+                    }
+
+                    present
+                });
+
+                // Likewise for anyone who stopped waiting in our lobby.  The server reminds us of anyone still
+                // waiting right after this.
+                ui.knocks.set(Vec::new());
 
                 update_remote_videos();
             }
 
-            ClientMessage::Peer { url, message } => {
+            ClientMessage::Remove { id } => {
+                connections.borrow_mut().remove(id);
+
+                // It may have been waiting in our lobby rather than in the room.
+                ui.knocks
+                    .update(|knocks| knocks.retain(|(knocker, _)| **knocker != *id));
+
+                update_remote_videos();
+            }
+
+            ClientMessage::Peer { from, message } => {
                 let connection = connections
                     .borrow()
-                    .get(url)
+                    .get(from)
                     .map(|c| Ok(c.connection.clone()));
 
                 if let Err(e) = handle_peer_message(
-                    me,
-                    chat_log,
-                    url,
-                    connection.unwrap_or_else(|| add_connection(url))?,
+                    signaling,
+                    ui.chat_log,
+                    from,
+                    connection.unwrap_or_else(|| add_connection(from))?,
                     message,
                 )
                 .await
                 {
-                    log::warn!("error accepting offer from {url}: {e:?}");
+                    log::warn!("error accepting offer from {from}: {e:?}");
                 }
             }
         },
@@ -652,7 +1214,7 @@ async fn handle_message(
     Ok(())
 }
 
-fn websocket_url() -> Result<String, MyError> {
+fn websocket_url(bridge_host: &str) -> Result<String, MyError> {
     let location = web_sys::window().unwrap().location();
 
     let base = format!(
@@ -665,18 +1227,17 @@ fn websocket_url() -> Result<String, MyError> {
     );
 
     Ok(format!(
-        "wss://{}/connect?f={base}/frame&d={base}/disconnect",
-        env!("WEBSOCKET_BRIDGE_HOST")
+        "wss://{bridge_host}/connect?f={base}/frame&d={base}/disconnect"
     ))
 }
 
 async fn connect(
     cx: Scope,
-    me: Rc<OnceCell<Box<str>>>,
+    config: Rc<ClientConfig>,
+    signaling: Rc<Signaling>,
+    mut outbound: UnboundedReceiver<Message>,
     connections: Rc<RefCell<HashMap<Rc<str>, Connection>>>,
-    local_video: WriteSignal<Option<MediaStream>>,
-    remote_videos: WriteSignal<Vec<(u64, ReadSignal<MediaStream>)>>,
-    chat_log: WriteSignal<ChatLog>,
+    ui: Ui,
 ) -> Result<(), MyError> {
     let window = web_sys::window().unwrap();
 
@@ -693,36 +1254,115 @@ async fn connect(
     .await?
     .dyn_into::<MediaStream>()?;
 
-    local_video.set(Some(local_stream.clone()));
-
-    let (mut tx, rx) = WebSocket::open(&websocket_url()?)?.split();
-
-    tx.send(Message::Text(serde_json::to_string(
-        &ServerMessage::Room {
-            name: &window.location().href()?,
-        },
-    )?))
-    .await?;
+    ui.local_video.set(Some(local_stream.clone()));
 
     let mut add_connection = make_connection_adder(
         cx,
         connections.clone(),
-        me.clone(),
-        remote_videos,
+        signaling.clone(),
+        ui.remote_videos,
         local_stream,
+        &config.ice_servers,
     );
 
-    let update_remote_videos = make_remote_video_updater(connections.clone(), remote_videos);
+    let update_remote_videos = make_remote_video_updater(connections.clone(), ui.remote_videos);
+
+    let mut delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        ui.status.set(Status::Connecting);
+
+        match session(
+            &config,
+            &signaling,
+            &mut outbound,
+            &connections,
+            ui,
+            &mut add_connection,
+            &update_remote_videos,
+        )
+        .await
+        {
+            // There's no point in retrying if the server doesn't speak our protocol or doesn't want us back.
+            Err(
+                e @ (MyError::Rejected { .. }
+                | MyError::Kicked
+                | MyError::RoomFull
+                | MyError::RoomLocked
+                | MyError::Denied
+                | MyError::PasswordRequired
+                | MyError::InviteRequired
+                | MyError::Unauthenticated),
+            ) => return Err(e),
+
+            Err(e) => log::warn!("lost connection to server: {e:?}"),
+
+            Ok(()) => log::warn!("server closed connection"),
+        }
+
+        if signaling.welcomed.get() {
+            delay = MIN_RECONNECT_DELAY;
+        }
+
+        ui.status.set(Status::Reconnecting);
+
+        log::info!("reconnecting in {delay:?}");
+
+        Delay::new(delay).await?;
+
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Open a websocket connection to the server and handle messages until it is closed or lost.
+///
+/// Any peer connections established during previous sessions are left alone, and we ask the server to let us
+/// resume the identity it gave us, so a brief outage need not interrupt calls already in progress.
+async fn session(
+    config: &ClientConfig,
+    signaling: &Signaling,
+    outbound: &mut UnboundedReceiver<Message>,
+    connections: &RefCell<HashMap<Rc<str>, Connection>>,
+    ui: Ui,
+    add_connection: &mut dyn (FnMut(&str) -> Result<RtcPeerConnection, MyError>),
+    update_remote_videos: &dyn (Fn()),
+) -> Result<(), MyError> {
+    signaling.welcomed.set(false);
+    signaling.expect_pongs.set(false);
+    signaling.unanswered_pings.set(0);
+
+    // Anything queued while we were disconnected belongs to the old session and would only confuse the server.
+    while let Ok(Some(_)) = outbound.try_next() {}
+
+    let (mut tx, rx) = WebSocket::open(&websocket_url(&config.websocket_bridge_host)?)?.split();
+
+    // Announce which protocol version we speak; we'll join the room once the server welcomes us.
+    {
+        let me = signaling.me.borrow();
+
+        signaling.send(&ServerMessage::Hello {
+            version: PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+            resume: me.as_ref().map(|me| Resume {
+                id: &me.id,
+                secret: &me.secret,
+            }),
+        })?;
+    }
 
     let ping_intervals = stream::unfold((), |()| Delay::new(PING_INTERVAL).map(|r| Some((r, ()))));
 
     enum Item {
         Message(Message),
+        Outbound(Message),
         Ping,
     }
 
     let mut rx = stream::select(
-        ping_intervals.map(|r| r.map(|()| Item::Ping).map_err(MyError::from)),
+        stream::select(
+            ping_intervals.map(|r| r.map(|()| Item::Ping).map_err(MyError::from)),
+            outbound.by_ref().map(|message| Ok(Item::Outbound(message))),
+        ),
         rx.map(|r| r.map(Item::Message).map_err(MyError::from)),
     );
 
@@ -730,19 +1370,32 @@ async fn connect(
         match item {
             Item::Message(message) => {
                 handle_message(
-                    &connections,
-                    &me,
-                    chat_log,
-                    &mut add_connection,
-                    &update_remote_videos,
+                    signaling,
+                    connections,
+                    ui,
+                    add_connection,
+                    update_remote_videos,
                     message,
                 )
                 .await?
             }
 
+            Item::Outbound(message) => tx.send(message).await?,
+
             Item::Ping => {
                 // Time to ping the server.  This is to avoid idle connection timeouts which might be imposed by
-                // intermediate proxies.
+                // intermediate proxies, and, if the server answers pings, to detect when it or the bridge has
+                // gone away without closing the websocket.
+
+                if signaling.expect_pongs.get() {
+                    if signaling.unanswered_pings.get() >= MAX_UNANSWERED_PINGS {
+                        return Err(MyError::Unresponsive);
+                    }
+
+                    signaling
+                        .unanswered_pings
+                        .set(signaling.unanswered_pings.get() + 1);
+                }
 
                 tx.send(Message::Text(serde_json::to_string(&ServerMessage::Ping)?))
                     .await?;
@@ -1,4 +1,25 @@
-use serde::{Deserialize, Serialize};
+use {
+    serde::{Deserialize, Serialize},
+    std::borrow::Cow,
+};
+
+/// Version of the signaling protocol spoken by this crate.  Bump this (and, if old clients can no longer be
+/// served, `MIN_PROTOCOL_VERSION`) whenever a message changes in a way an older peer would misinterpret.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Oldest client protocol version the server is still willing to talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// Runtime configuration served to clients by `GET /config`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientConfig {
+    pub websocket_bridge_host: String,
+    pub ice_servers: Vec<String>,
+    pub features: Vec<String>,
+    /// Where to send users who need to sign in before joining a room, if anywhere.
+    #[serde(default)]
+    pub login_url: Option<String>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -22,24 +43,142 @@ pub enum PeerMessage<'a> {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientMessage<'a> {
+    Welcome {
+        version: u32,
+        features: Vec<String>,
+    },
+    Rejected {
+        min_version: u32,
+        max_version: u32,
+    },
     You {
-        url: &'a str,
+        id: &'a str,
+        secret: &'a str,
     },
     Add {
-        url: &'a str,
+        id: &'a str,
     },
     Remove {
-        url: &'a str,
+        id: &'a str,
+    },
+    /// IDs of everyone else in the recipient's room, sent when it rejoins the room after reconnecting so it can
+    /// hang up on peers which left while it was away.  If it hosts the room, `Knock`s for everyone still waiting
+    /// in the lobby follow.  Only sent to clients which negotiated the "members" feature.
+    Members {
+        #[serde(borrow)]
+        ids: Vec<&'a str>,
     },
     Peer {
-        url: &'a str,
+        from: &'a str,
         message: PeerMessage<'a>,
     },
+    Pong,
+    /// The recipient has been removed from its room and should not rejoin.  Only sent to clients which
+    /// negotiated the "kick" feature.
+    Kicked,
+    /// The room the recipient asked to join already has as many members as the server allows.  Only sent to
+    /// clients which negotiated the "room_full" feature.
+    RoomFull,
+    /// The room the recipient asked to join is password-protected, and it supplied neither the correct password
+    /// nor a valid invite.  Only sent to clients which negotiated the "password" feature.
+    PasswordRequired,
+    /// The room the recipient asked to join is invite-only, and it didn't supply a valid invite.  Only sent to
+    /// clients which negotiated the "invite" feature.
+    InviteRequired,
+    /// The server requires authentication, and the recipient's token was missing, invalid, or not valid for the
+    /// room it asked to join.  Only sent to clients which negotiated the "token" feature.
+    Unauthenticated,
+    /// `id` is the host of the recipient's room, i.e. the member allowed to moderate it.  Sent when the recipient
+    /// joins and whenever the host changes.  Only sent to clients which negotiated the "host" feature, as are the
+    /// other host-related messages below.
+    Host {
+        id: &'a str,
+    },
+    /// The host asks the recipient to mute its microphone.
+    Mute,
+    /// The host locked or unlocked the recipient's room.  Nobody new may join a locked room.
+    Locked {
+        locked: bool,
+    },
+    /// The room the recipient asked to join is locked.
+    RoomLocked,
+    /// The peer identified by `id` (and, if the server requires authentication, `user`) is waiting in the lobby
+    /// of the room the recipient hosts.  The host should either `ServerMessage::Admit` or `ServerMessage::Deny`
+    /// it, and gets a `Remove` for it once it has left the lobby, whether because it was admitted, was denied, or
+    /// gave up waiting.  If the room is full, admitting it has no effect besides a repeat of this message.  Only
+    /// sent to clients which negotiated the "lobby" feature, as are the other lobby-related messages below.
+    ///
+    /// `user` is a `Cow` since it comes from the identity provider, and so may need escaping.
+    Knock {
+        id: &'a str,
+        #[serde(borrow, default)]
+        user: Option<Cow<'a, str>>,
+    },
+    /// The recipient is waiting for the host to let it into the room it asked to join.
+    Waiting,
+    /// The host of the room the recipient asked to join turned it away.
+    Denied,
+}
+
+/// Identity a reconnecting client asks to keep, as given to it by `ClientMessage::You`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Resume<'a> {
+    pub id: &'a str,
+    pub secret: &'a str,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerMessage<'a> {
-    Room { name: &'a str },
+    Hello {
+        version: u32,
+        features: Vec<String>,
+        #[serde(borrow)]
+        resume: Option<Resume<'a>>,
+    },
+    /// Join the room with the specified ID, i.e. the `{id}` in `/room/{id}`.
+    ///
+    /// If the room is password-protected, either `password` or `invite` must be valid, and if it's invite-only,
+    /// `invite` must be.  If the room is empty, `password` (if any) becomes its password.  If the server requires
+    /// authentication, `token` must be a JWT from its identity provider.
+    ///
+    /// The credentials are `Cow`s since they may contain characters which must be escaped in JSON, and so can't
+    /// always be borrowed from the frame.
+    Room {
+        name: &'a str,
+        #[serde(borrow, default)]
+        password: Option<Cow<'a, str>>,
+        #[serde(borrow, default)]
+        invite: Option<Cow<'a, str>>,
+        #[serde(borrow, default)]
+        token: Option<Cow<'a, str>>,
+    },
+    Signal {
+        to: &'a str,
+        message: PeerMessage<'a>,
+    },
     Ping,
+    /// Remove the peer identified by `id` from the sender's room.  Only honored if the sender is the room's host,
+    /// as are the other moderation commands below.
+    Kick {
+        id: &'a str,
+    },
+    /// Ask everyone else in the sender's room to mute.
+    MuteAll,
+    /// Lock or unlock the sender's room.
+    Lock {
+        locked: bool,
+    },
+    /// Make the peer identified by `to` the host of the sender's room instead.
+    TransferHost {
+        to: &'a str,
+    },
+    /// Let the peer identified by `id` into the sender's room from its lobby.
+    Admit {
+        id: &'a str,
+    },
+    /// Turn the peer identified by `id` away from the sender's room.
+    Deny {
+        id: &'a str,
+    },
 }
@@ -0,0 +1,161 @@
+//! Admin API for inspecting rooms and kicking peers, authenticated using the `admin_token` variable.
+//!
+//! - `GET /admin/rooms` lists rooms and how many members each has.
+//! - `GET /admin/rooms/{room}` lists the members of a room, along with its host, whether it's locked, and who's
+//!   waiting in its lobby.
+//! - `DELETE /admin/rooms/{room}/members/{peer}` kicks a peer out of a room.
+//! - `POST /admin/rooms/{room}/invites` mints an invite token for a room (see `credentials::mint_invite`).
+//! - `PUT /admin/rooms/{room}/invite_only` makes a room admit only peers with an invite, and `DELETE` undoes that.
+//!
+//! Rooms are identified by the same IDs clients use (see `room_name`).
+
+use {
+    crate::{
+        credentials::{self, constant_time_eq},
+        error::ServerError,
+        kick, now, room_id, room_name, settings,
+        store::RoomStore,
+    },
+    anyhow::Result,
+    http::{Method, StatusCode},
+    serde_json::{json, Value},
+    spin_sdk::http::{Request, Response},
+};
+
+/// Handle a request whose path starts with `/admin/`.
+pub fn route(store: &dyn RoomStore, req: &Request) -> Result<Response> {
+    authenticate(req)?;
+
+    let segments = req
+        .uri()
+        .path()
+        .trim_start_matches("/admin/")
+        .split('/')
+        .collect::<Vec<_>>();
+
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["rooms"]) => rooms(store),
+
+        (&Method::GET, ["rooms", id]) => room_members(store, id),
+
+        (&Method::DELETE, ["rooms", id, "members", peer]) => {
+            let room = room_name(id).ok_or(ServerError::NotFound)?;
+
+            if kick(store, &room, peer)? {
+                no_content()
+            } else {
+                Err(ServerError::NotFound.into())
+            }
+        }
+
+        (&Method::POST, ["rooms", id, "invites"]) => {
+            room_name(id).ok_or(ServerError::NotFound)?;
+
+            let expires = now() + settings::invite_ttl()?;
+
+            json(json!({
+                "invite": credentials::mint_invite(id, expires)?,
+                "expires": expires,
+            }))
+        }
+
+        (&Method::PUT, ["rooms", id, "invite_only"]) => {
+            let room = room_name(id).ok_or(ServerError::NotFound)?;
+
+            if settings::invite_secret()?.is_empty() {
+                return Err(ServerError::BadRequest(
+                    "invites are disabled; set `invite_secret` to enable them".into(),
+                )
+                .into());
+            }
+
+            store.set_invite_only(&room, true)?;
+
+            no_content()
+        }
+
+        (&Method::DELETE, ["rooms", id, "invite_only"]) => {
+            let room = room_name(id).ok_or(ServerError::NotFound)?;
+
+            store.set_invite_only(&room, false)?;
+
+            no_content()
+        }
+
+        _ => Err(ServerError::NotFound.into()),
+    }
+}
+
+fn no_content() -> Result<Response> {
+    Ok(http::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(None)?)
+}
+
+/// Check that `req` carries the admin token, failing with `ServerError::Forbidden` if none is configured.
+pub fn authenticate(req: &Request) -> Result<()> {
+    let token = settings::admin_token()?;
+
+    if token.is_empty() {
+        return Err(ServerError::Forbidden.into());
+    }
+
+    let presented = req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match presented {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err(ServerError::Unauthorized.into()),
+    }
+}
+
+fn json(value: Value) -> Result<Response> {
+    Ok(http::Response::builder()
+        .header("content-type", "application/json")
+        .body(Some(serde_json::to_vec(&value)?.into()))?)
+}
+
+fn rooms(store: &dyn RoomStore) -> Result<Response> {
+    let mut rooms = store.rooms()?;
+    rooms.sort();
+
+    json(Value::Array(
+        rooms
+            .iter()
+            .filter_map(|room| room_id(room).map(|id| (room, id)))
+            .map(|(room, id)| Ok(json!({ "room": id, "members": store.members(room)?.len() })))
+            .collect::<Result<_>>()?,
+    ))
+}
+
+fn room_members(store: &dyn RoomStore, id: &str) -> Result<Response> {
+    let room = room_name(id).ok_or(ServerError::NotFound)?;
+    let mut members = store.members(&room)?;
+
+    if members.is_empty() {
+        return Err(ServerError::NotFound.into());
+    }
+
+    members.sort();
+
+    json(json!({
+        "room": id,
+        "host": store.host(&room)?,
+        "locked": store.locked(&room)?,
+        "invite_only": store.invite_only(&room)?,
+        "lobby": store.lobby(&room)?,
+        "members": members
+            .iter()
+            .map(|member| {
+                Ok(json!({
+                    "id": member,
+                    "user": store.user(member)?,
+                    "last_seen": store.last_seen(member)?,
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?,
+    }))
+}
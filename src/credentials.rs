@@ -0,0 +1,99 @@
+//! Room passwords and invite tokens.
+//!
+//! Passwords are stored as salted PBKDF2 hashes so that a leaked store doesn't reveal them.  Invites are tokens
+//! of the form `{expiry}.{signature}`, where the signature is an HMAC (keyed by the `invite_secret` variable) of
+//! the room ID and expiry time, so they can be verified without storing anything.
+
+use {
+    crate::{error::ServerError, settings},
+    anyhow::{anyhow, Result},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    hmac::{Hmac, Mac},
+    sha2::Sha256,
+};
+
+/// PBKDF2 iterations used when hashing room passwords.
+const PASSWORD_ROUNDS: u32 = 100_000;
+
+/// Compare `a` and `b` in time which doesn't depend on where they differ, so secrets can't be guessed a byte at
+/// a time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn derive(password: &str, salt: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PASSWORD_ROUNDS, &mut hash);
+    hash
+}
+
+/// Hash `password` with a fresh random salt, returning a string suitable for `verify_password`.
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow!("unable to generate salt: {e}"))?;
+
+    Ok(format!(
+        "{}${}",
+        URL_SAFE_NO_PAD.encode(salt),
+        URL_SAFE_NO_PAD.encode(derive(password, &salt))
+    ))
+}
+
+/// Determine whether `password` matches `hashed`, as returned by `hash_password`.
+pub fn verify_password(hashed: &str, password: &str) -> Result<bool> {
+    let (salt, hash) = hashed
+        .split_once('$')
+        .ok_or_else(|| anyhow!("malformed password hash"))?;
+
+    Ok(constant_time_eq(
+        &derive(password, &URL_SAFE_NO_PAD.decode(salt)?),
+        &URL_SAFE_NO_PAD.decode(hash)?,
+    ))
+}
+
+/// Start computing the signature for an invite, or return `None` if invites are disabled (i.e. `invite_secret` is
+/// empty).
+fn invite_mac(room_id: &str, expiry: u64) -> Result<Option<Hmac<Sha256>>> {
+    let secret = settings::invite_secret()?;
+
+    if secret.is_empty() {
+        return Ok(None);
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("{room_id}:{expiry}").as_bytes());
+
+    Ok(Some(mac))
+}
+
+/// Mint a token which admits its bearer to the room identified by `room_id` until `expiry` (in seconds since the
+/// Unix epoch).
+pub fn mint_invite(room_id: &str, expiry: u64) -> Result<String> {
+    let mac = invite_mac(room_id, expiry)?.ok_or(ServerError::Forbidden)?;
+
+    Ok(format!(
+        "{expiry}.{}",
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// Determine whether `token` is a valid, unexpired invite to the room identified by `room_id`.
+pub fn verify_invite(room_id: &str, token: &str, now: u64) -> Result<bool> {
+    let Some((expiry, signature)) = token.split_once('.') else {
+        return Ok(false);
+    };
+
+    let (Ok(expiry), Ok(signature)) = (expiry.parse::<u64>(), URL_SAFE_NO_PAD.decode(signature))
+    else {
+        return Ok(false);
+    };
+
+    if expiry < now {
+        return Ok(false);
+    }
+
+    Ok(match invite_mac(room_id, expiry)? {
+        Some(mac) => mac.verify_slice(&signature).is_ok(),
+        None => false,
+    })
+}
@@ -0,0 +1,81 @@
+//! Errors reported to HTTP clients by `handle`.
+
+use {
+    crate::store::StoreError, anyhow::Result, http::StatusCode, spin_sdk::http::Response,
+    thiserror::Error,
+};
+
+/// An error which prevented us from handling a request, classified by whose fault it was.
+///
+/// Code deeper in the call stack returns `anyhow::Error`s as usual; those which wrap a `ServerError` (or a
+/// `StoreError` anywhere in their chain) are classified accordingly, and everything else is an internal error.
+#[derive(Error, Debug)]
+pub enum ServerError {
+    /// The request was malformed, e.g. a frame which isn't valid JSON or a missing `ws-bridge-send` header.
+    #[error("bad request: {0}")]
+    BadRequest(String),
+
+    /// The request lacked valid credentials.
+    #[error("unauthorized")]
+    Unauthorized,
+
+    /// The request's credentials don't permit what it asked for.
+    #[error("forbidden")]
+    Forbidden,
+
+    /// The requested resource (e.g. a room or peer) doesn't exist.
+    #[error("not found")]
+    NotFound,
+
+    /// The sender exceeded a rate limit.
+    #[error("too many requests")]
+    TooManyRequests,
+
+    /// The room store is unreachable or misbehaving.
+    #[error("room store unavailable")]
+    StoreUnavailable(#[source] anyhow::Error),
+
+    #[error("internal server error")]
+    Internal(#[source] anyhow::Error),
+}
+
+impl ServerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::StoreUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Build a response with the appropriate status code and a JSON body of the form `{"error": "..."}`.
+    ///
+    /// Only the `Display` form of the error is included; details of internal and store errors stay in the logs.
+    pub fn response(&self) -> Result<Response> {
+        Ok(http::Response::builder()
+            .status(self.status())
+            .header("content-type", "application/json")
+            .body(Some(
+                serde_json::to_vec(&serde_json::json!({ "error": self.to_string() }))?.into(),
+            ))?)
+    }
+}
+
+impl From<anyhow::Error> for ServerError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<ServerError>() {
+            Ok(error) => error,
+            Err(error) => {
+                if error.chain().any(|cause| cause.is::<StoreError>()) {
+                    Self::StoreUnavailable(error)
+                } else {
+                    Self::Internal(error)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Verification of JSON Web Tokens issued by an external identity provider.
+//!
+//! Tokens are verified offline against keys configured via Spin variables: an HS256 shared secret
+//! (`jwt_hs256_secret`) and/or an RS256 JSON Web Key Set (`jwt_jwks`).  Requiring tokens is enabled by
+//! configuring either.
+
+use {
+    crate::settings,
+    anyhow::Result,
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    hmac::{Hmac, Mac},
+    rsa::{
+        pkcs1v15::{Signature, VerifyingKey},
+        signature::Verifier,
+        BigUint, RsaPublicKey,
+    },
+    serde_json::Value,
+    sha2::Sha256,
+    thiserror::Error,
+};
+
+/// Reason a token was rejected.
+#[derive(Error, Debug)]
+#[error("invalid token: {0}")]
+pub struct InvalidToken(&'static str);
+
+/// Claims we care about from a verified token.
+pub struct Claims {
+    /// Identity of the user, i.e. the `sub` claim.
+    pub subject: String,
+    /// IDs of the rooms the user may join, from the `rooms` claim, or `None` if the token doesn't restrict them.
+    pub rooms: Option<Vec<String>>,
+}
+
+impl Claims {
+    pub fn allows(&self, room_id: &str) -> bool {
+        self.rooms
+            .as_ref()
+            .map(|rooms| rooms.iter().any(|room| room == room_id || room == "*"))
+            .unwrap_or(true)
+    }
+}
+
+/// Determine whether peers must present a token in order to join a room.
+pub fn enabled() -> Result<bool> {
+    Ok(!settings::get("jwt_hs256_secret")?.is_empty() || !settings::get("jwt_jwks")?.is_empty())
+}
+
+fn decode_json(part: &str) -> Result<Value> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| InvalidToken("malformed base64"))?;
+
+    Ok(serde_json::from_slice(&bytes).map_err(|_| InvalidToken("malformed JSON"))?)
+}
+
+/// Verify `token`'s signature and its `exp`, `nbf`, `iss`, and `aud` claims, returning the claims we care about.
+///
+/// Fails with `InvalidToken` if the token is unacceptable for any reason.
+pub fn verify(token: &str, now: u64) -> Result<Claims> {
+    let mut parts = token.split('.');
+
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(InvalidToken("expected three parts").into());
+    };
+
+    let header = decode_json(header)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| InvalidToken("malformed signature"))?;
+    let signed = &token[..token.rfind('.').unwrap_or(0)];
+
+    let valid = match header["alg"].as_str() {
+        Some("HS256") => verify_hs256(signed, &signature)?,
+        Some("RS256") => verify_rs256(header["kid"].as_str(), signed, &signature)?,
+        // Notably, this rejects "none".
+        _ => return Err(InvalidToken("unsupported algorithm").into()),
+    };
+
+    if !valid {
+        return Err(InvalidToken("bad signature").into());
+    }
+
+    let claims = decode_json(payload)?;
+
+    match claims["exp"].as_u64() {
+        Some(exp) if exp >= now => (),
+        Some(_) => return Err(InvalidToken("expired").into()),
+        None => return Err(InvalidToken("missing exp").into()),
+    }
+
+    if claims["nbf"].as_u64().map(|nbf| nbf > now).unwrap_or(false) {
+        return Err(InvalidToken("not yet valid").into());
+    }
+
+    let issuer = settings::get("jwt_issuer")?;
+
+    if !issuer.is_empty() && claims["iss"].as_str() != Some(issuer.as_str()) {
+        return Err(InvalidToken("wrong issuer").into());
+    }
+
+    let audience = settings::get("jwt_audience")?;
+
+    if !audience.is_empty() {
+        let matches = match &claims["aud"] {
+            Value::String(aud) => *aud == audience,
+            Value::Array(auds) => auds
+                .iter()
+                .any(|aud| aud.as_str() == Some(audience.as_str())),
+            _ => false,
+        };
+
+        if !matches {
+            return Err(InvalidToken("wrong audience").into());
+        }
+    }
+
+    let subject = claims["sub"]
+        .as_str()
+        .ok_or(InvalidToken("missing sub"))?
+        .to_owned();
+
+    let rooms = match &claims["rooms"] {
+        Value::Null => None,
+        Value::Array(rooms) => Some(
+            rooms
+                .iter()
+                .filter_map(|room| room.as_str().map(str::to_owned))
+                .collect(),
+        ),
+        _ => return Err(InvalidToken("malformed rooms claim").into()),
+    };
+
+    Ok(Claims { subject, rooms })
+}
+
+fn verify_hs256(signed: &str, signature: &[u8]) -> Result<bool> {
+    let secret = settings::get("jwt_hs256_secret")?;
+
+    if secret.is_empty() {
+        return Ok(false);
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(signed.as_bytes());
+
+    Ok(mac.verify_slice(signature).is_ok())
+}
+
+fn verify_rs256(kid: Option<&str>, signed: &str, signature: &[u8]) -> Result<bool> {
+    let jwks = settings::get("jwt_jwks")?;
+
+    if jwks.is_empty() {
+        return Ok(false);
+    }
+
+    let jwks = serde_json::from_str::<Value>(&jwks)?;
+    let Ok(signature) = Signature::try_from(signature) else {
+        return Ok(false);
+    };
+
+    // If the token names a key, use only that one; otherwise, try each RSA key in turn.
+    for key in jwks["keys"].as_array().into_iter().flatten() {
+        if key["kty"].as_str() != Some("RSA") || (kid.is_some() && key["kid"].as_str() != kid) {
+            continue;
+        }
+
+        let (Some(n), Some(e)) = (key["n"].as_str(), key["e"].as_str()) else {
+            continue;
+        };
+
+        let key = RsaPublicKey::new(
+            BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(n)?),
+            BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(e)?),
+        )?;
+
+        if VerifyingKey::<Sha256>::new(key)
+            .verify(signed.as_bytes(), &signature)
+            .is_ok()
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
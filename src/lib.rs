@@ -1,90 +1,1429 @@
 #![deny(warnings)]
 use {
-    anyhow::{anyhow, Context, Error, Result},
-    http::{response::Builder, HeaderMap, Method, StatusCode},
+    anyhow::{anyhow, bail, Error, Result},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    credentials::constant_time_eq,
+    error::ServerError,
+    http::{response::Builder, HeaderMap, Method, StatusCode, Uri},
+    jwt::InvalidToken,
+    logging::Level,
+    platform::Spin,
+    settings::DeliveryPolicy,
     spin_sdk::{
         http::{Request, Response},
-        http_component, outbound_http, redis,
+        http_component,
+        outbound_http::OutboundHttpError,
     },
-    spin_webrtc_protocol::{ClientMessage, ServerMessage},
-    std::{env, fs, str},
+    spin_webrtc_protocol::{
+        ClientMessage, PeerMessage, Resume, ServerMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    },
+    std::{
+        borrow::Cow,
+        fs,
+        rc::Rc,
+        thread,
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    },
+    store::{KeyValueStore, LazyStore, RedisStore, RoomStore},
 };
 
-const REDIS_URL: &str = env!("REDIS_URL");
+mod admin;
+mod credentials;
+pub mod error;
+mod jwt;
+mod logging;
+mod metrics;
+mod platform;
+mod rate_limit;
+mod settings;
+mod store;
+#[cfg(test)]
+mod tests;
+
+/// Optional protocol features this server knows how to handle; see `ServerMessage::Hello`.
+const FEATURES: &[&str] = &[
+    "chat",
+    "pong",
+    "kick",
+    "room_full",
+    "password",
+    "token",
+    "host",
+    "lobby",
+    "invite",
+    "members",
+];
+
+/// Open the `RoomStore` selected by the `room_store` config variable.
+fn open_store() -> Result<Box<dyn RoomStore>> {
+    Ok(match settings::get("room_store")?.as_str() {
+        "redis" => {
+            let url = settings::get("redis_url")?;
+
+            if url.is_empty() {
+                bail!("redis_url must be set when room_store is redis");
+            }
+
+            Box::new(RedisStore::new(url))
+        }
+        "key_value" => Box::new(KeyValueStore::open("default")?),
+        other => bail!("unknown room_store: {other}"),
+    })
+}
 
+/// Get the websocket-bridge URL from the specified header, provided it belongs to one of the `bridge_origins`.
+///
+/// We POST to these URLs later, so accepting arbitrary ones would let anyone make requests on our behalf.
 fn get_header_url<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str> {
-    headers
+    let url = headers
         .get(name)
-        .with_context(|| anyhow!(r#"missing required header: "{name}""#))?
+        .ok_or_else(|| ServerError::BadRequest(format!(r#"missing required header: "{name}""#)))?
         .to_str()
-        .with_context(|| anyhow!(r#"unable to parse "{name}" header as UTF-8"#))
+        .map_err(|_| {
+            ServerError::BadRequest(format!(r#"unable to parse "{name}" header as UTF-8"#))
+        })?;
+
+    let trusted = match origin(url) {
+        Some(presented) => settings::bridge_origins()?
+            .iter()
+            .any(|trusted| origin(trusted).as_ref() == Some(&presented)),
+        None => false,
+    };
+
+    if !trusted {
+        logging::warn("untrusted_bridge").url(url).emit();
+
+        return Err(ServerError::Forbidden.into());
+    }
+
+    Ok(url)
+}
+
+/// Normalize the scheme and authority of the specified URL, e.g. `HTTPS://Bridge.example.com:9443/send/abc` ->
+/// `https://bridge.example.com:9443`.
+fn origin(url: &str) -> Option<String> {
+    let uri = url.parse::<Uri>().ok()?;
+
+    Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?).to_ascii_lowercase())
+}
+
+/// Verify that a request to `/frame` or `/disconnect` came from our websocket-bridge, if `bridge_secret` is set.
+fn authenticate_bridge(headers: &HeaderMap) -> Result<()> {
+    let secret = settings::bridge_secret()?;
+
+    if secret.is_empty() {
+        return Ok(());
+    }
+
+    match headers.get("x-bridge-secret") {
+        Some(presented) if constant_time_eq(presented.as_bytes(), secret.as_bytes()) => Ok(()),
+        _ => Err(ServerError::Unauthorized.into()),
+    }
+}
+
+/// Get the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Get the current time in milliseconds since the Unix epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Generate a random, URL-safe token, e.g. for peer IDs and secrets.
+///
+/// Peers only ever learn each other's IDs; the websocket-bridge send URLs which the IDs map to stay on the
+/// server so that nobody can push frames to a peer except via this app.
+fn random_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow!("unable to generate token: {e}"))?;
+
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn hello(
+    store: &dyn RoomStore,
+    url: &str,
+    version: u32,
+    features: &[String],
+    resume: Option<Resume>,
+) -> Result<()> {
+    logging::debug("hello")
+        .url(url)
+        .field("version", version)
+        .field("features", features)
+        .emit();
+
+    if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        let id = match resume {
+            // A client which lost its websocket connection may keep its ID (and thus its membership and peer
+            // connections) if it knows the secret and we haven't already removed it.
+            Some(Resume { id, secret }) if knows_secret(store, id, secret)? => {
+                logging::info("resume").peer(id).url(url).emit();
+
+                id.to_owned()
+            }
+
+            _ => {
+                if let Some(id) = store.peer(url)? {
+                    id
+                } else {
+                    let id = random_token()?;
+
+                    store.set_secret(&id, &random_token()?)?;
+
+                    id
+                }
+            }
+        };
+
+        let features = features
+            .iter()
+            .filter(|feature| FEATURES.contains(&feature.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        store.connect(url, &id, version, &features)?;
+        store.touch(&id, now())?;
+
+        send_to(store, url, &ClientMessage::Welcome { version, features })
+    } else {
+        reject(store, url)
+    }
+}
+
+/// Determine whether `secret` is the one we gave `peer` along with its ID.
+fn knows_secret(store: &dyn RoomStore, peer: &str, secret: &str) -> Result<bool> {
+    Ok(match store.secret(peer)? {
+        Some(known) => constant_time_eq(known.as_bytes(), secret.as_bytes()),
+        None => false,
+    })
+}
+
+fn reject(store: &dyn RoomStore, url: &str) -> Result<()> {
+    logging::info("reject").url(url).emit();
+
+    send_to(
+        store,
+        url,
+        &ClientMessage::Rejected {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        },
+    )
+}
+
+/// Longest room ID we accept.
+const MAX_ROOM_ID_LENGTH: usize = 64;
+
+/// Map a client-supplied room ID to the name we store that room under, or `None` if the ID is invalid.
+///
+/// IDs are limited to characters which are safe in both URLs and store keys, and the names are namespaced so
+/// they can't collide with whatever other kinds of names we store rooms under in the future.
+fn room_name(id: &str) -> Option<String> {
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ROOM_ID_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+    valid.then(|| format!("room/{id}"))
+}
+
+/// Inverse of `room_name`.
+fn room_id(name: &str) -> Option<&str> {
+    name.strip_prefix("room/")
+}
+
+/// Determine whether a peer presenting the specified credentials may join `room`, whose ID is `room_id`.
+fn authorized(
+    store: &dyn RoomStore,
+    room: &str,
+    room_id: &str,
+    password: Option<&str>,
+    invite: Option<&str>,
+) -> Result<bool> {
+    if let Some(invite) = invite {
+        if credentials::verify_invite(room_id, invite, now())? {
+            return Ok(true);
+        }
+    }
+
+    if store.invite_only(room)? {
+        return Ok(false);
+    }
+
+    match (store.room_password(room)?, password) {
+        (None, _) => Ok(true),
+        (Some(hashed), Some(password)) => {
+            // Checking a password is deliberately slow, so guessing one mustn't be allowed to tie us up.
+            let limit = settings::password_rate_limit()?;
+
+            if !rate_limit::take(store, &format!("rate:password:{room}"), &limit, now_ms())? {
+                logging::warn("rate_limited")
+                    .room(room)
+                    .field("scope", "password")
+                    .emit();
+
+                record_metric(metrics::rate_limited(store, "password"));
+
+                return Err(ServerError::TooManyRequests.into());
+            }
+
+            credentials::verify_password(&hashed, password)
+        }
+        (Some(_), None) => Ok(false),
+    }
+}
+
+fn add(
+    store: &dyn RoomStore,
+    url: &str,
+    room_id: &str,
+    password: Option<&str>,
+    invite: Option<&str>,
+    token: Option<&str>,
+) -> Result<()> {
+    // Clients which predate the handshake (e.g. stale cached copies) never send `ServerMessage::Hello`, and
+    // thus never get an ID, so we refuse to let them join rather than risk them misinterpreting what we send.
+    let Some(id) = store.peer(url)? else {
+        return reject(store, url);
+    };
+
+    if room_id.is_empty() {
+        return Ok(());
+    }
+
+    let Some(room) = room_name(room_id) else {
+        logging::warn("invalid_room")
+            .peer(&id)
+            .field("room_id", room_id)
+            .emit();
+
+        return Ok(());
+    };
+    let room = room.as_str();
+
+    let secret = store
+        .secret(&id)?
+        .ok_or_else(|| anyhow!("missing secret for {id}"))?;
+
+    send_to(
+        store,
+        url,
+        &ClientMessage::You {
+            id: &id,
+            secret: &secret,
+        },
+    )?;
+
+    let current = store.room_of(&id)?;
+
+    if current.as_deref() == Some(room) {
+        logging::debug("rejoin").room(room).peer(&id).emit();
+
+        // The peer may have missed changes while it was reconnecting, since we don't deliver to disconnected
+        // peers, so remind it who's here.  It ignores `Add`s for peers it's still connected to.
+        let members = store.members(room)?;
+        let others = members
+            .iter()
+            .map(String::as_str)
+            .filter(|&member| member != id)
+            .collect::<Vec<_>>();
+
+        for &member in &others {
+            send_to(store, url, &ClientMessage::Add { id: member })?;
+        }
+
+        if store.supports(&id, "members")? {
+            send_to(store, url, &ClientMessage::Members { ids: others })?;
+        }
+
+        send_host_state(store, url, &id, room)?;
+
+        // Likewise, a host may have missed knocks.
+        if store.host(room)?.as_deref() == Some(id.as_str()) {
+            send_knocks(store, room, &id)?;
+        }
+
+        return Ok(());
+    }
+
+    if store.lobby(room)?.contains(&id) {
+        logging::debug("rejoin_lobby").room(room).peer(&id).emit();
+
+        if store.supports(&id, "lobby")? {
+            send_to(store, url, &ClientMessage::Waiting)?;
+        }
+
+        return Ok(());
+    }
+
+    if jwt::enabled()? {
+        let claims = match token.map(|token| jwt::verify(token, now())) {
+            Some(Ok(claims)) => Some(claims),
+            Some(Err(e)) if e.is::<InvalidToken>() => {
+                logging::info("invalid_token")
+                    .room(room)
+                    .peer(&id)
+                    .field("error", e.to_string())
+                    .emit();
+
+                None
+            }
+            Some(Err(e)) => return Err(e),
+            None => None,
+        };
+
+        match claims {
+            Some(claims) if claims.allows(room_id) => store.set_user(&id, &claims.subject)?,
+
+            _ => {
+                logging::info("unauthenticated").room(room).peer(&id).emit();
+
+                if store.supports(&id, "token")? {
+                    send_to(store, url, &ClientMessage::Unauthenticated)?;
+                }
+
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(user) = store.user(&id)? {
+        if store.banned(room, &user)? {
+            logging::info("banned").room(room).peer(&id).emit();
+
+            if store.supports(&id, "kick")? {
+                send_to(store, url, &ClientMessage::Kicked)?;
+            }
+
+            return Ok(());
+        }
+    }
+
+    if !authorized(store, room, room_id, password, invite)? {
+        logging::info("unauthorized").room(room).peer(&id).emit();
+
+        if store.invite_only(room)? {
+            if store.supports(&id, "invite")? {
+                send_to(store, url, &ClientMessage::InviteRequired)?;
+            }
+        } else if store.supports(&id, "password")? {
+            send_to(store, url, &ClientMessage::PasswordRequired)?;
+        }
+
+        return Ok(());
+    }
+
+    if store.locked(room)? {
+        logging::info("room_locked").room(room).peer(&id).emit();
+
+        if store.supports(&id, "host")? {
+            send_to(store, url, &ClientMessage::RoomLocked)?;
+        }
+
+        return Ok(());
+    }
+
+    if !charge_room(store, room, now_ms())? {
+        logging::warn("rate_limited")
+            .room(room)
+            .peer(&id)
+            .field("scope", "room")
+            .emit();
+
+        record_metric(metrics::rate_limited(store, "room"));
+
+        return Err(ServerError::TooManyRequests.into());
+    }
+
+    if room_full(store, room)? {
+        logging::info("room_full").room(room).peer(&id).emit();
+
+        if store.supports(&id, "room_full")? {
+            send_to(store, url, &ClientMessage::RoomFull)?;
+        }
+
+        return Ok(());
+    }
+
+    let size = store.members(room)?.len();
+
+    // Once someone's in the room, newcomers may have to wait for its host to let them in.  That requires a host
+    // which knows how to do so, though.
+    if size > 0 && settings::lobby()? {
+        if let Some(host) = store.host(room)? {
+            if store.supports(&host, "lobby")? {
+                return knock(store, url, &id, room, &host);
+            }
+        }
+    }
+
+    enter(store, url, &id, room)?;
+
+    // The first member to join an empty room decides whether it's password-protected.
+    if let (0, Some(password)) = (size, password) {
+        logging::info("set_password").room(room).peer(&id).emit();
+
+        store.set_room_password(room, &credentials::hash_password(password)?)?;
+    }
+
+    Ok(())
 }
 
-fn add(url: &str, room: &str) -> Result<()> {
-    println!("add {url} to {room}\n");
+/// Check whether `room` already has as many members as we allow.
+fn room_full(store: &dyn RoomStore, room: &str) -> Result<bool> {
+    // Every client connects directly to every other member of its room, so large rooms overwhelm them.  Note
+    // that concurrent joins may briefly push a room past its limit, since we don't check and join atomically.
+    let max_room_size = settings::max_room_size()?;
 
-    // TODO: check if specified URL is already in a room and either remove it or error out
+    Ok(max_room_size > 0 && store.members(room)?.len() >= max_room_size)
+}
+
+/// Add `id` (connected via `url`) to `room`, moving it out of whatever room it was in before.
+fn enter(store: &dyn RoomStore, url: &str, id: &str, room: &str) -> Result<()> {
+    match store.room_of(id)? {
+        Some(current) => {
+            logging::info("move")
+                .room(room)
+                .peer(id)
+                .field("from_room", current.as_str())
+                .emit();
 
-    if !room.is_empty() {
-        send_to(url, &ClientMessage::You { url })?;
+            store.leave(id)?;
+            record_metric(metrics::left(store));
 
-        redis::sadd(REDIS_URL, &format!("room:{room}"), &[url]).map_err(redis_error)?;
+            send_to_all(
+                store,
+                &current,
+                Some(id),
+                None,
+                &ClientMessage::Remove { id },
+            )?;
 
-        redis::set(REDIS_URL, &format!("url:{url}"), room.as_bytes()).map_err(redis_error)?;
+            // Tell the peer to hang up on its former roommates, too.
+            for member in store.members(&current)? {
+                send_to(store, url, &ClientMessage::Remove { id: &member })?;
+            }
 
-        send_to_all(url, room, &ClientMessage::Add { url })?;
+            hand_off_host(store, &current, id)?;
+        }
+
+        None => logging::info("join").room(room).peer(id).emit(),
+    }
+
+    store.join(room, id)?;
+    record_metric(metrics::joined(store));
+
+    // The first member to join also becomes its host.
+    if store.host(room)?.is_none() {
+        logging::info("host").room(room).peer(id).emit();
+
+        store.set_host(room, id)?;
+    }
+
+    send_host_state(store, url, id, room)?;
+
+    send_to_all(store, room, Some(id), None, &ClientMessage::Add { id }).map(drop)
+}
+
+/// Put `id` (connected via `url`) in the lobby of `room` and ask `host` to let it in.
+fn knock(store: &dyn RoomStore, url: &str, id: &str, room: &str, host: &str) -> Result<()> {
+    logging::info("knock").room(room).peer(id).emit();
+
+    // A peer can only wait to get into one room at a time.
+    withdraw_knock(store, id)?;
+
+    store.knock(room, id)?;
+
+    if store.supports(id, "lobby")? {
+        send_to(store, url, &ClientMessage::Waiting)?;
+    }
+
+    send_knock(store, host, id)
+}
+
+/// Tell `host` about everyone waiting in the lobby of `room`, provided it knows how to let them in.
+fn send_knocks(store: &dyn RoomStore, room: &str, host: &str) -> Result<()> {
+    if store.supports(host, "lobby")? {
+        for knocker in store.lobby(room)? {
+            send_knock(store, host, &knocker)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tell `host` that `id` is waiting in its room's lobby.
+fn send_knock(store: &dyn RoomStore, host: &str, id: &str) -> Result<()> {
+    if let Some(url) = store.url(host)? {
+        send_to(
+            store,
+            &url,
+            &ClientMessage::Knock {
+                id,
+                user: store.user(id)?.map(Cow::Owned),
+            },
+        )?;
     }
 
     Ok(())
 }
 
-fn remove(url: &str) -> Result<()> {
-    let room = redis::get(REDIS_URL, &format!("url:{url}")).map_err(redis_error)?;
-    let room = str::from_utf8(&room)?;
+/// Take `id` out of whatever lobby it's waiting in, if any, and tell that room's host it's no longer waiting.
+fn withdraw_knock(store: &dyn RoomStore, id: &str) -> Result<()> {
+    if let Some(room) = store.leave_lobby(id)? {
+        logging::info("withdraw_knock").room(&room).peer(id).emit();
 
-    if !room.is_empty() {
-        println!("remove {url} from {room}\n");
+        if let Some(host) = store.host(&room)? {
+            if let Some(url) = store.url(&host)? {
+                send_to(store, &url, &ClientMessage::Remove { id })?;
+            }
+        }
+    }
 
-        redis::del(REDIS_URL, &[&format!("url:{url}")]).map_err(redis_error)?;
+    Ok(())
+}
 
-        redis::srem(REDIS_URL, &format!("room:{room}"), &[url]).map_err(redis_error)?;
+/// Tell `id` (connected via `url`) who hosts `room` and whether it's locked, if it negotiated the "host" feature.
+fn send_host_state(store: &dyn RoomStore, url: &str, id: &str, room: &str) -> Result<()> {
+    if !store.supports(id, "host")? {
+        return Ok(());
+    }
 
-        send_to_all(url, room, &ClientMessage::Remove { url })?;
+    if let Some(host) = store.host(room)? {
+        send_to(store, url, &ClientMessage::Host { id: &host })?;
+    }
+
+    if store.locked(room)? {
+        send_to(store, url, &ClientMessage::Locked { locked: true })?;
     }
 
     Ok(())
 }
 
-fn send_to(url: &str, outbound: &ClientMessage) -> Result<()> {
-    println!("send to {url}: {outbound:?}\n");
+/// Make `host` the host of `room` and tell its members, passing along anyone waiting in its lobby.
+fn set_host(store: &dyn RoomStore, room: &str, host: &str) -> Result<()> {
+    logging::info("host").room(room).peer(host).emit();
 
-    let response = outbound_http::send_request(
-        http::Request::builder()
-            .method("POST")
-            .uri(url)
-            .header("content-type", "text/plain;charset=UTF-8")
-            .body(Some(serde_json::to_string(outbound)?.into()))?,
+    store.set_host(room, host)?;
+
+    send_to_all(
+        store,
+        room,
+        None,
+        Some("host"),
+        &ClientMessage::Host { id: host },
     )?;
 
-    if response.status() == StatusCode::NOT_FOUND {
-        remove(url)?;
+    send_knocks(store, room, host)
+}
+
+/// If `former` was the host of `room`, which it just left, pick one of the remaining members to take over.
+///
+/// If nobody's left, anyone waiting in the room's lobby is turned away, since there's no one to let them in.
+fn hand_off_host(store: &dyn RoomStore, room: &str, former: &str) -> Result<()> {
+    let members = store.members(room)?;
+
+    if members.is_empty() {
+        for knocker in store.lobby(room)? {
+            deny(store, room, &knocker)?;
+        }
+
+        return Ok(());
+    }
+
+    if store.host(room)?.as_deref() != Some(former) {
+        return Ok(());
+    }
+
+    // We don't keep track of who joined when, so any member will do.
+    match members.into_iter().min() {
+        Some(host) => set_host(store, room, &host),
+        None => Ok(()),
+    }
+}
+
+/// Turn `knocker` away from the lobby of `room`.
+fn deny(store: &dyn RoomStore, room: &str, knocker: &str) -> Result<()> {
+    logging::info("deny").room(room).peer(knocker).emit();
+
+    withdraw_knock(store, knocker)?;
+
+    if let Some(url) = store.url(knocker)? {
+        if store.supports(knocker, "lobby")? {
+            send_to(store, &url, &ClientMessage::Denied)?;
+        }
     }
 
     Ok(())
 }
 
-fn send_to_all(url: &str, room: &str, outbound: &ClientMessage) -> Result<()> {
-    for member in redis::smembers(REDIS_URL, &format!("room:{room}")).map_err(redis_error)? {
-        if member != url {
-            send_to(&member, outbound)?;
+/// Get the ID of the peer connected via `url` and the room it hosts, or log and return `None` if it isn't a host.
+fn hosted_room(
+    store: &dyn RoomStore,
+    url: &str,
+    command: &'static str,
+) -> Result<Option<(String, String)>> {
+    let drop_command = |reason: &'static str| {
+        logging::warn("drop_command")
+            .url(url)
+            .field("command", command)
+            .field("reason", reason)
+            .emit()
+    };
+
+    let Some(id) = store.peer(url)? else {
+        drop_command("unknown sender");
+        return Ok(None);
+    };
+
+    let Some(room) = store.room_of(&id)? else {
+        drop_command("sender not in a room");
+        return Ok(None);
+    };
+
+    if store.host(&room)?.as_deref() != Some(id.as_str()) {
+        drop_command("sender not host");
+        return Ok(None);
+    }
+
+    Ok(Some((id, room)))
+}
+
+fn host_kick(store: &dyn RoomStore, url: &str, peer: &str) -> Result<()> {
+    let Some((host, room)) = hosted_room(store, url, "kick")? else {
+        return Ok(());
+    };
+
+    let anonymous = store.user(peer)?.is_none();
+
+    if peer == host || !kick(store, &room, peer)? {
+        logging::warn("drop_command")
+            .room(&room)
+            .peer(&host)
+            .field("command", "kick")
+            .field("reason", "target not in room")
+            .emit();
+    } else if anonymous {
+        // We can't ban a peer we can't identify, so the best the host can do to keep it out is lock the room.
+        set_locked(store, &room, true)?;
+    }
+
+    Ok(())
+}
+
+/// Get the ID of the peer connected via `url` and the room it hosts, provided `knocker` is waiting in its lobby.
+fn hosted_lobby(
+    store: &dyn RoomStore,
+    url: &str,
+    command: &'static str,
+    knocker: &str,
+) -> Result<Option<(String, String)>> {
+    let Some((host, room)) = hosted_room(store, url, command)? else {
+        return Ok(None);
+    };
+
+    if !store.lobby(&room)?.iter().any(|id| id == knocker) {
+        logging::warn("drop_command")
+            .room(&room)
+            .peer(&host)
+            .field("command", command)
+            .field("reason", "target not in lobby")
+            .emit();
+
+        return Ok(None);
+    }
+
+    Ok(Some((host, room)))
+}
+
+fn admit(store: &dyn RoomStore, url: &str, knocker: &str) -> Result<()> {
+    let Some((host, room)) = hosted_lobby(store, url, "admit", knocker)? else {
+        return Ok(());
+    };
+
+    let Some(knocker_url) = store.url(knocker)? else {
+        // It's lost its connection.  If it reconnects in time, it'll knock again.
+        return withdraw_knock(store, knocker);
+    };
+
+    // If the room has filled up in the meantime, the knocker stays in the lobby, none the wiser, and we remind
+    // the host it's still waiting so it can try again later.
+    if room_full(store, &room)? {
+        logging::info("admit_room_full")
+            .room(&room)
+            .peer(knocker)
+            .emit();
+
+        return send_knock(store, &host, knocker);
+    }
+
+    logging::info("admit").room(&room).peer(knocker).emit();
+
+    withdraw_knock(store, knocker)?;
+
+    enter(store, &knocker_url, knocker, &room)
+}
+
+fn host_deny(store: &dyn RoomStore, url: &str, knocker: &str) -> Result<()> {
+    match hosted_lobby(store, url, "deny", knocker)? {
+        Some((_, room)) => deny(store, &room, knocker),
+        None => Ok(()),
+    }
+}
+
+fn mute_all(store: &dyn RoomStore, url: &str) -> Result<()> {
+    let Some((host, room)) = hosted_room(store, url, "mute_all")? else {
+        return Ok(());
+    };
+
+    logging::info("mute_all").room(&room).peer(&host).emit();
+
+    send_to_all(
+        store,
+        &room,
+        Some(&host),
+        Some("host"),
+        &ClientMessage::Mute,
+    )
+    .map(drop)
+}
+
+fn lock(store: &dyn RoomStore, url: &str, locked: bool) -> Result<()> {
+    let Some((host, room)) = hosted_room(store, url, "lock")? else {
+        return Ok(());
+    };
+
+    logging::info("lock")
+        .room(&room)
+        .peer(&host)
+        .field("locked", locked)
+        .emit();
+
+    set_locked(store, &room, locked)
+}
+
+/// Lock or unlock `room` and tell its members.
+fn set_locked(store: &dyn RoomStore, room: &str, locked: bool) -> Result<()> {
+    store.set_locked(room, locked)?;
+
+    send_to_all(
+        store,
+        room,
+        None,
+        Some("host"),
+        &ClientMessage::Locked { locked },
+    )
+    .map(drop)
+}
+
+fn transfer_host(store: &dyn RoomStore, url: &str, to: &str) -> Result<()> {
+    let Some((host, room)) = hosted_room(store, url, "transfer_host")? else {
+        return Ok(());
+    };
+
+    if to == host || store.room_of(to)?.as_deref() != Some(room.as_str()) {
+        logging::warn("drop_command")
+            .room(&room)
+            .peer(&host)
+            .field("command", "transfer_host")
+            .field("reason", "target not in room")
+            .emit();
+
+        return Ok(());
+    }
+
+    set_host(store, &room, to)
+}
+
+/// Handle websocket-bridge telling us the client connected via `url` has gone away.
+///
+/// A peer which was in a room (or waiting in a lobby) keeps its place until `sweep` evicts it `presence_ttl`
+/// seconds from now, so that a client whose connection merely dropped can resume where it left off.
+fn disconnect(store: &dyn RoomStore, url: &str) -> Result<()> {
+    let id = store.peer(url)?;
+
+    store.disconnect(url, id.as_deref())?;
+
+    let Some(id) = id else {
+        return Ok(());
+    };
+
+    if store.room_of(&id)?.is_some() || store.lobby_of(&id)?.is_some() {
+        logging::info("disconnect").peer(&id).emit();
+
+        store.touch(&id, now())
+    } else {
+        store.forget(&id)
+    }
+}
+
+/// Evict the peer connected via `url` from its room (or lobby) and forget it.
+fn remove(store: &dyn RoomStore, url: &str) -> Result<()> {
+    if let Some(id) = store.peer(url)? {
+        remove_peer(store, &id)?;
+        store.forget(&id)
+    } else {
+        Ok(())
+    }
+}
+
+fn remove_peer(store: &dyn RoomStore, id: &str) -> Result<()> {
+    withdraw_knock(store, id)?;
+
+    if let Some(room) = store.leave(id)? {
+        record_metric(metrics::left(store));
+
+        logging::info("leave").room(&room).peer(id).emit();
+
+        send_to_all(store, &room, Some(id), None, &ClientMessage::Remove { id })?;
+
+        hand_off_host(store, &room, id)?;
+    }
+
+    Ok(())
+}
+
+/// Remove `peer` from `room` at an administrator's (or the room host's) behest, telling it not to come back.
+///
+/// Returns `false` if `peer` wasn't in `room`.
+fn kick(store: &dyn RoomStore, room: &str, peer: &str) -> Result<bool> {
+    if store.room_of(peer)?.as_deref() != Some(room) {
+        return Ok(false);
+    }
+
+    logging::info("kick").room(room).peer(peer).emit();
+
+    // If we know which user the peer belongs to, make sure it can't just rejoin by banning that user from the room
+    // (even under a new peer ID) until everyone has left.
+    if let Some(user) = store.user(peer)? {
+        store.ban(room, &user)?;
+    }
+
+    remove_peer(store, peer)?;
+
+    if let Some(url) = store.url(peer)? {
+        // Have the peer hang up on its former roommates (which works even for clients which don't understand
+        // `ClientMessage::Kicked`) before telling it why.
+        for member in store.members(room)? {
+            send_to(store, &url, &ClientMessage::Remove { id: &member })?;
+        }
+
+        if store.supports(peer, "kick")? {
+            send_to(store, &url, &ClientMessage::Kicked)?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Charge a frame from `url` against its sender's rate limit and, for frames which fan out to the sender's room,
+/// that room's.
+///
+/// Frames from unknown senders (i.e. `Hello`s from new connections) are charged to their connection instead, at
+/// the same rate as peers.  Joins are charged to the room they ask to join by `add`, and only once they've been
+/// authorized, so that outsiders can't use up a room's budget.  Frames over a limit are rejected with
+/// `ServerError::TooManyRequests`, and peers over their own limit are also kicked and disconnected if
+/// `rate_limit_disconnect` is set.
+fn throttle(store: &dyn RoomStore, url: &str, message: &ServerMessage) -> Result<()> {
+    let now = now_ms();
+
+    let Some(peer) = store.peer(url)? else {
+        if rate_limit::take(
+            store,
+            &format!("rate:url:{url}"),
+            &settings::peer_rate_limit()?,
+            now,
+        )? {
+            return Ok(());
+        }
+
+        logging::warn("rate_limited")
+            .url(url)
+            .field("scope", "connection")
+            .emit();
+
+        record_metric(metrics::rate_limited(store, "connection"));
+
+        return Err(ServerError::TooManyRequests.into());
+    };
+
+    let scope = if !rate_limit::take(
+        store,
+        &format!("rate:peer:{peer}"),
+        &settings::peer_rate_limit()?,
+        now,
+    )? {
+        "peer"
+    } else {
+        let room = match message {
+            ServerMessage::Signal { .. }
+            | ServerMessage::Kick { .. }
+            | ServerMessage::MuteAll
+            | ServerMessage::Lock { .. }
+            | ServerMessage::TransferHost { .. }
+            | ServerMessage::Admit { .. }
+            | ServerMessage::Deny { .. } => store.room_of(&peer)?,
+            ServerMessage::Hello { .. } | ServerMessage::Room { .. } | ServerMessage::Ping => None,
+        };
+
+        match room {
+            Some(room) if !charge_room(store, &room, now)? => "room",
+            _ => return Ok(()),
+        }
+    };
+
+    logging::warn("rate_limited")
+        .peer(&peer)
+        .field("scope", scope)
+        .emit();
+
+    record_metric(metrics::rate_limited(store, scope));
+
+    if scope == "peer" && settings::rate_limit_disconnect()? {
+        if let Some(room) = store.room_of(&peer)? {
+            kick(store, &room, &peer)?;
+        }
+
+        remove(store, url)?;
+    }
+
+    Err(ServerError::TooManyRequests.into())
+}
+
+/// Charge a frame which fans out to `room` against that room's rate limit, returning `false` if it's over.
+fn charge_room(store: &dyn RoomStore, room: &str, now: u64) -> Result<bool> {
+    rate_limit::take(
+        store,
+        &format!("rate:room:{room}"),
+        &settings::room_rate_limit()?,
+        now,
+    )
+}
+
+fn ping(store: &dyn RoomStore, url: &str) -> Result<()> {
+    if let Some(id) = store.peer(url)? {
+        store.touch(&id, now())?;
+
+        if store.supports(&id, "pong")? {
+            send_to(store, url, &ClientMessage::Pong)?;
+        }
+
+        if let Some(room) = store.room_of(&id)? {
+            sweep(store, &room)?;
         }
     }
 
     Ok(())
 }
 
-fn redis_error(_error: redis::Error) -> Error {
-    anyhow!("redis error")
+/// Evict any members of `room` (or peers waiting in its lobby) we haven't heard from recently.
+///
+/// websocket-bridge normally tells us when a peer disconnects, but that won't happen if the bridge crashes or is
+/// partitioned from us, so we also rely on the pings each client sends periodically.
+fn sweep(store: &dyn RoomStore, room: &str) -> Result<()> {
+    let deadline = now().saturating_sub(settings::presence_ttl()?);
+
+    for member in store.members(room)? {
+        if store.last_seen(&member)?.unwrap_or(0) < deadline {
+            logging::info("evict")
+                .room(room)
+                .peer(&member)
+                .field("reason", "inactive")
+                .emit();
+
+            record_metric(metrics::evicted(store, "inactive"));
+
+            remove_peer(store, &member)?;
+            store.forget(&member)?;
+        }
+    }
+
+    for knocker in store.lobby(room)? {
+        if store.last_seen(&knocker)?.unwrap_or(0) < deadline {
+            withdraw_knock(store, &knocker)?;
+            store.forget(&knocker)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Relay a `PeerMessage` from the peer connected via `url` to the peer identified by `to`.
+///
+/// Peers may only signal other members of the room they've joined.  Anything else is logged and dropped.
+fn signal(store: &dyn RoomStore, url: &str, to: &str, message: PeerMessage) -> Result<()> {
+    let Some(from) = store.peer(url)? else {
+        logging::warn("drop_signal")
+            .url(url)
+            .field("reason", "unknown sender")
+            .emit();
+        return Ok(());
+    };
+
+    let Some(room) = store.room_of(&from)? else {
+        logging::warn("drop_signal")
+            .peer(&from)
+            .field("reason", "sender not in a room")
+            .emit();
+        return Ok(());
+    };
+
+    if store.room_of(to)?.as_deref() != Some(room.as_str()) {
+        logging::warn("drop_signal")
+            .room(&room)
+            .peer(&from)
+            .field("to", to)
+            .field("reason", "recipient not in room")
+            .emit();
+        return Ok(());
+    }
+
+    let Some(to_url) = store.url(to)? else {
+        logging::warn("drop_signal")
+            .room(&room)
+            .peer(&from)
+            .field("to", to)
+            .field("reason", "unknown recipient")
+            .emit();
+        return Ok(());
+    };
+
+    logging::debug("relay_signal")
+        .room(&room)
+        .peer(&from)
+        .field("to", to)
+        .emit();
+
+    send_to(
+        store,
+        &to_url,
+        &ClientMessage::Peer {
+            from: &from,
+            message,
+        },
+    )
+}
+
+/// What happened when we tried to deliver a message to a peer.
+enum Delivery {
+    Delivered,
+    /// The bridge no longer knows about the peer, i.e. it has disconnected.
+    Gone,
+}
+
+/// Why a single attempt to deliver a message to a peer failed.
+enum Failure {
+    /// The failure may be temporary (e.g. a refused connection or a 5xx response), so it's worth retrying.
+    Transient(Error),
+    /// Retrying won't help (e.g. the URL is invalid or not allowed by the Spin manifest).
+    Permanent(Error),
+}
+
+/// Tally of a `send_to_all` broadcast.
+#[derive(Default)]
+struct Summary {
+    delivered: usize,
+    /// Members we evicted because they had disconnected or repeatedly failed to receive messages.
+    evicted: Vec<String>,
+    /// Members we were unable to reach for some other reason, along with that reason.
+    failed: Vec<(String, Error)>,
+}
+
+/// Get the name of `message`'s type, for logging.  We don't log whole messages since some contain secrets.
+fn message_type(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Welcome { .. } => "welcome",
+        ClientMessage::Rejected { .. } => "rejected",
+        ClientMessage::You { .. } => "you",
+        ClientMessage::Add { .. } => "add",
+        ClientMessage::Remove { .. } => "remove",
+        ClientMessage::Members { .. } => "members",
+        ClientMessage::Peer { .. } => "peer",
+        ClientMessage::Pong => "pong",
+        ClientMessage::Kicked => "kicked",
+        ClientMessage::RoomFull => "room_full",
+        ClientMessage::PasswordRequired => "password_required",
+        ClientMessage::InviteRequired => "invite_required",
+        ClientMessage::Unauthenticated => "unauthenticated",
+        ClientMessage::Host { .. } => "host",
+        ClientMessage::Mute => "mute",
+        ClientMessage::Locked { .. } => "locked",
+        ClientMessage::RoomLocked => "room_locked",
+        ClientMessage::Knock { .. } => "knock",
+        ClientMessage::Waiting => "waiting",
+        ClientMessage::Denied => "denied",
+    }
+}
+
+fn send_to(store: &dyn RoomStore, url: &str, outbound: &ClientMessage) -> Result<()> {
+    logging::debug("send")
+        .url(url)
+        .field("message", message_type(outbound))
+        .emit();
+
+    let policy = settings::delivery_policy()?;
+
+    match post(store, &policy, url, &serde_json::to_string(outbound)?) {
+        Ok(Delivery::Delivered) => {
+            if let Some(peer) = store.peer(url)? {
+                store.record_success(&peer)?;
+            }
+
+            Ok(())
+        }
+
+        Ok(Delivery::Gone) => {
+            record_metric(metrics::evicted(store, "gone"));
+
+            remove(store, url)
+        }
+
+        Err(e) => {
+            if let Some(peer) = store.peer(url)? {
+                if store.record_failure(&peer)? >= policy.max_failures {
+                    logging::info("evict")
+                        .peer(&peer)
+                        .field("reason", "undeliverable")
+                        .emit();
+
+                    record_metric(metrics::evicted(store, "undeliverable"));
+
+                    remove(store, url)?;
+                }
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// POST `body` to the specified websocket-bridge URL, retrying transient failures as `policy` allows.
+fn post(store: &dyn RoomStore, policy: &DeliveryPolicy, url: &str, body: &str) -> Result<Delivery> {
+    let mut delay = policy.retry_delay;
+    let mut attempt = 1;
+
+    loop {
+        match try_post(store, url, body) {
+            Ok(delivery) => break Ok(delivery),
+
+            Err(Failure::Transient(e)) if attempt < policy.attempts => {
+                logging::warn("retry_delivery")
+                    .url(url)
+                    .field("attempt", attempt)
+                    .field("delay_ms", delay.as_millis() as u64)
+                    .field("error", format!("{e:?}"))
+                    .emit();
+
+                thread::sleep(delay);
+
+                delay *= 2;
+                attempt += 1;
+            }
+
+            Err(Failure::Transient(e) | Failure::Permanent(e)) => break Err(e),
+        }
+    }
+}
+
+/// Make a single attempt to POST `body` to the specified websocket-bridge URL, recording metrics as we go.
+fn try_post(store: &dyn RoomStore, url: &str, body: &str) -> Result<Delivery, Failure> {
+    let request = http::Request::builder()
+        .method("POST")
+        .uri(url)
+        .header("content-type", "text/plain;charset=UTF-8")
+        .body(Some(body.to_owned().into()))
+        .map_err(|e| Failure::Permanent(e.into()))?;
+
+    let start = Instant::now();
+    let response = platform::send(request);
+
+    record_metric(metrics::delivery_latency(store, start.elapsed()));
+
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            record_metric(metrics::delivery_failed(store, "error"));
+
+            return Err(match e {
+                OutboundHttpError::DestinationNotAllowed | OutboundHttpError::InvalidUrl => {
+                    Failure::Permanent(e.into())
+                }
+                _ => Failure::Transient(e.into()),
+            });
+        }
+    };
+
+    let status = response.status();
+
+    if !status.is_success() {
+        record_metric(metrics::delivery_failed(store, status.as_str()));
+    }
+
+    if status.is_success() {
+        Ok(Delivery::Delivered)
+    } else if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+        Ok(Delivery::Gone)
+    } else {
+        let error = anyhow!("websocket-bridge responded with {status}");
+
+        if status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS
+        {
+            Err(Failure::Transient(error))
+        } else {
+            Err(Failure::Permanent(error))
+        }
+    }
+}
+
+/// Log a failure to record a metric rather than failing whatever it measures over it.
+fn record_metric(result: Result<()>) {
+    if let Err(e) = result {
+        logging::warn("metric_failed")
+            .field("error", format!("{e:?}"))
+            .emit();
+    }
+}
+
+/// Send `outbound` to each member of `room` (other than `except`, if specified) which negotiated `feature` (if
+/// specified), returning the member ID, URL, and outcome for each.
+///
+/// Spin's outbound HTTP interface is blocking and guests are single-threaded, so the requests are necessarily
+/// issued one after another.  We resolve every member's URL and serialize the message before sending anything,
+/// though, so that this is the only function which needs to change once the host supports concurrent requests.
+///
+/// Unlike `send_to`, we make only one attempt per member, since retrying (and sleeping between retries) for one
+/// struggling member would hold up delivery to everyone after it.  A member which keeps missing messages is
+/// evicted by `send_to_all` soon enough.
+fn broadcast(
+    store: &dyn RoomStore,
+    room: &str,
+    except: Option<&str>,
+    feature: Option<&str>,
+    outbound: &ClientMessage,
+) -> Result<Vec<(String, String, Result<Delivery>)>> {
+    logging::debug("broadcast")
+        .room(room)
+        .field("message", message_type(outbound))
+        .emit();
+
+    let body = serde_json::to_string(outbound)?;
+
+    let mut targets = Vec::new();
+
+    for member in store.members(room)? {
+        if Some(member.as_str()) == except {
+            continue;
+        }
+
+        if let Some(feature) = feature {
+            if !store.supports(&member, feature)? {
+                continue;
+            }
+        }
+
+        if let Some(url) = store.url(&member)? {
+            targets.push((member, url));
+        }
+    }
+
+    Ok(targets
+        .into_iter()
+        .map(|(member, url)| {
+            let result = try_post(store, &url, &body)
+                .map_err(|(Failure::Transient(e) | Failure::Permanent(e))| e);
+
+            (member, url, result)
+        })
+        .collect())
+}
+
+/// Send `outbound` to each member of `room` (other than `except`, if specified) which negotiated `feature` (if
+/// specified), evicting any members which have disconnected.
+///
+/// Delivery is best-effort: failing to reach one member neither stops us from trying the rest nor fails the
+/// request which prompted the broadcast.  Members which fail to receive too many messages in a row are evicted,
+/// per `settings::delivery_policy`.  Failures are logged and reported in the returned `Summary`.
+fn send_to_all(
+    store: &dyn RoomStore,
+    room: &str,
+    except: Option<&str>,
+    feature: Option<&str>,
+    outbound: &ClientMessage,
+) -> Result<Summary> {
+    let policy = settings::delivery_policy()?;
+    let mut summary = Summary::default();
+    let mut gone = Vec::new();
+
+    for (member, url, result) in broadcast(store, room, except, feature, outbound)? {
+        match result {
+            Ok(Delivery::Delivered) => {
+                store.record_success(&member)?;
+
+                summary.delivered += 1;
+            }
+
+            Ok(Delivery::Gone) => {
+                record_metric(metrics::evicted(store, "gone"));
+
+                gone.push((member, url))
+            }
+
+            Err(e) => {
+                if store.record_failure(&member)? >= policy.max_failures {
+                    logging::info("evict")
+                        .room(room)
+                        .peer(&member)
+                        .field("reason", "undeliverable")
+                        .emit();
+
+                    record_metric(metrics::evicted(store, "undeliverable"));
+
+                    gone.push((member.clone(), url));
+                }
+
+                summary.failed.push((member, e));
+            }
+        }
+    }
+
+    // We evict only once the broadcast is complete, since each eviction involves a broadcast of its own.
+    for (member, url) in gone {
+        if let Err(e) = remove(store, &url) {
+            summary.failed.push((member, e));
+        } else {
+            summary.evicted.push(member);
+        }
+    }
+
+    logging::debug("broadcast_summary")
+        .room(room)
+        .field("delivered", summary.delivered)
+        .field("evicted", summary.evicted.len())
+        .field("failed", summary.failed.len())
+        .emit();
+
+    for (member, e) in &summary.failed {
+        logging::warn("delivery_failed")
+            .room(room)
+            .peer(member)
+            .field("message", message_type(outbound))
+            .field("error", format!("{e:?}"))
+            .emit();
+    }
+
+    Ok(summary)
 }
 
 fn response() -> Builder {
@@ -111,32 +1450,118 @@ fn content_type(path: &str) -> &'static str {
 
 #[http_component]
 fn handle(req: Request) -> Result<Response> {
-    let send_url = || get_header_url(req.headers(), "ws-bridge-send");
+    platform::install(Rc::new(Spin));
+
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .map_or_else(random_token, Ok)?;
+
+    logging::begin(request_id, settings::log_level().unwrap_or(Level::Info));
+
+    let response = route(&LazyStore::new(open_store), &req).or_else(|e| {
+        let error = ServerError::from(e);
+
+        logging::error("error")
+            .field("status", error.status().as_u16())
+            .field("error", format!("{error:?}"))
+            .emit();
 
-    println!("got request: {req:?}\n");
+        error.response()
+    })?;
+
+    logging::info("request")
+        .field("method", req.method().as_str())
+        .field("path", req.uri().path())
+        .field("status", response.status().as_u16())
+        .emit();
+
+    Ok(response)
+}
+
+fn route(store: &dyn RoomStore, req: &Request) -> Result<Response> {
+    let send_url = || get_header_url(req.headers(), "ws-bridge-send");
 
     Ok(match (req.method(), req.uri().path()) {
         (&Method::POST, "/frame") => {
+            authenticate_bridge(req.headers())?;
+
             let message = serde_json::from_slice(
                 req.body()
                     .as_deref()
-                    .ok_or_else(|| anyhow!("expected non-empty body"))?,
-            )?;
+                    .ok_or_else(|| ServerError::BadRequest("expected non-empty body".into()))?,
+            )
+            .map_err(|e| ServerError::BadRequest(format!("malformed frame: {e}")))?;
+
+            throttle(store, send_url()?, &message)?;
 
             match message {
-                ServerMessage::Room { name } => add(send_url()?, name)?,
-                ServerMessage::Ping => (),
+                ServerMessage::Hello {
+                    version,
+                    features,
+                    resume,
+                } => hello(store, send_url()?, version, &features, resume)?,
+                ServerMessage::Room {
+                    name,
+                    password,
+                    invite,
+                    token,
+                } => add(
+                    store,
+                    send_url()?,
+                    name,
+                    password.as_deref(),
+                    invite.as_deref(),
+                    token.as_deref(),
+                )?,
+                ServerMessage::Signal { to, message } => signal(store, send_url()?, to, message)?,
+                ServerMessage::Ping => ping(store, send_url()?)?,
+                ServerMessage::Kick { id } => host_kick(store, send_url()?, id)?,
+                ServerMessage::MuteAll => mute_all(store, send_url()?)?,
+                ServerMessage::Lock { locked } => lock(store, send_url()?, locked)?,
+                ServerMessage::TransferHost { to } => transfer_host(store, send_url()?, to)?,
+                ServerMessage::Admit { id } => admit(store, send_url()?, id)?,
+                ServerMessage::Deny { id } => host_deny(store, send_url()?, id)?,
             }
 
             response().body(None)?
         }
 
         (&Method::POST, "/disconnect") => {
-            remove(send_url()?)?;
+            authenticate_bridge(req.headers())?;
+
+            disconnect(store, send_url()?)?;
 
             response().body(None)?
         }
 
+        (&Method::POST, "/sweep") => {
+            // Sweeping every room is expensive, so only let the admin's cron job do it.
+            admin::authenticate(req)?;
+
+            for room in store.rooms()? {
+                sweep(store, &room)?;
+            }
+
+            response().body(None)?
+        }
+
+        (_, path) if path.starts_with("/admin/") => admin::route(store, req)?,
+
+        (&Method::GET, "/metrics") => response()
+            .header("content-type", "text/plain;version=0.0.4")
+            .body(Some(metrics::render(store)?.into()))?,
+
+        (&Method::GET, "/config") => {
+            response()
+                .header("content-type", "application/json")
+                .body(Some(
+                    serde_json::to_vec(&settings::client_config()?)?.into(),
+                ))?
+        }
+
         (&Method::GET, path) => {
             if let Ok(body) = fs::read(path) {
                 response()
@@ -149,6 +1574,10 @@ fn handle(req: Request) -> Result<Response> {
             }?
         }
 
-        _ => response().status(StatusCode::BAD_REQUEST).body(None)?,
+        (method, path) => {
+            return Err(
+                ServerError::BadRequest(format!("unsupported route: {method} {path}")).into(),
+            )
+        }
     })
 }
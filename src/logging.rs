@@ -0,0 +1,159 @@
+//! Structured logging: each event is written to stdout as a single line of JSON.
+//!
+//! Spin instantiates the component afresh for each request, so the per-request context (ID, start time, and
+//! configured level) lives in a thread-local which `begin` sets up.
+
+use {
+    http::Uri,
+    serde_json::{Map, Value},
+    std::{cell::RefCell, str::FromStr, time::Instant},
+    thiserror::Error,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("unknown log level: {0}")]
+pub struct UnknownLevel(String);
+
+impl FromStr for Level {
+    type Err = UnknownLevel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            _ => return Err(UnknownLevel(s.to_owned())),
+        })
+    }
+}
+
+struct Context {
+    request_id: String,
+    start: Instant,
+    level: Level,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Start logging on behalf of the request identified by `request_id`, omitting events less severe than `level`.
+pub fn begin(request_id: String, level: Level) {
+    CONTEXT.with(|context| {
+        *context.borrow_mut() = Some(Context {
+            request_id,
+            start: Instant::now(),
+            level,
+        })
+    });
+}
+
+/// Reduce a websocket-bridge URL to its scheme and authority.
+///
+/// The rest of the URL identifies a specific websocket connection and grants the ability to send frames to it, so
+/// it must never end up in the logs.
+pub fn redact(url: &str) -> String {
+    match url.parse::<Uri>() {
+        Ok(uri) => match (uri.scheme_str(), uri.authority()) {
+            (Some(scheme), Some(authority)) => format!("{scheme}://{authority}/[redacted]"),
+            _ => "[redacted]".into(),
+        },
+        Err(_) => "[redacted]".into(),
+    }
+}
+
+/// A log event under construction; nothing is logged until `emit` is called.
+#[must_use = "events are only logged when `emit` is called"]
+pub struct Event {
+    level: Level,
+    fields: Map<String, Value>,
+}
+
+impl Event {
+    fn new(level: Level, event: &str) -> Self {
+        Self {
+            level,
+            fields: Map::new(),
+        }
+        .field("event", event)
+    }
+
+    pub fn room(self, room: &str) -> Self {
+        self.field("room", room)
+    }
+
+    pub fn peer(self, peer: &str) -> Self {
+        self.field("peer", peer)
+    }
+
+    /// Record the websocket-bridge URL involved, redacted as described for `redact`.
+    pub fn url(self, url: &str) -> Self {
+        self.field("bridge", redact(url))
+    }
+
+    pub fn field(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.fields.insert(name.to_owned(), value.into());
+        self
+    }
+
+    /// Write the event to stdout if the configured level allows it.
+    pub fn emit(self) {
+        let Self { level, mut fields } = self;
+
+        CONTEXT.with(|context| {
+            let context = context.borrow();
+
+            if level > context.as_ref().map(|c| c.level).unwrap_or(Level::Info) {
+                return;
+            }
+
+            fields.insert("level".into(), level.as_str().into());
+
+            if let Some(context) = context.as_ref() {
+                fields.insert("request_id".into(), context.request_id.as_str().into());
+                fields.insert(
+                    "latency_ms".into(),
+                    (context.start.elapsed().as_millis() as u64).into(),
+                );
+            }
+
+            println!("{}", Value::Object(fields));
+        });
+    }
+}
+
+pub fn error(event: &str) -> Event {
+    Event::new(Level::Error, event)
+}
+
+pub fn warn(event: &str) -> Event {
+    Event::new(Level::Warn, event)
+}
+
+pub fn info(event: &str) -> Event {
+    Event::new(Level::Info, event)
+}
+
+pub fn debug(event: &str) -> Event {
+    Event::new(Level::Debug, event)
+}
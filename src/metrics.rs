@@ -0,0 +1,184 @@
+//! Prometheus metrics, kept in the `RoomStore` so that every instance of the component contributes to (and
+//! reports) the same totals.
+//!
+//! The totals are approximate: concurrent updates may overwrite each other, since the key-value backend can't
+//! increment atomically and `RoomStore::incr_by` (which accumulates the latency sum) isn't atomic on any
+//! backend, and metrics which fail to record are logged and dropped rather than failing whatever they measure.
+//! To keep the cost of each delivery attempt down, the latency histogram records only its bucket and sum,
+//! deriving the count from the buckets.
+
+use {
+    crate::store::RoomStore,
+    anyhow::Result,
+    std::{fmt::Write, time::Duration},
+};
+
+/// Upper bounds (in seconds) of the delivery latency histogram buckets, excluding the implicit `+Inf` bucket.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+struct Counter {
+    name: &'static str,
+    help: &'static str,
+    /// Whether this family's series carry a label, in which case none exist until the first increment.
+    labeled: bool,
+}
+
+const COUNTERS: &[Counter] = &[
+    Counter {
+        name: "spin_webrtc_joins_total",
+        help: "Times a peer joined a room.",
+        labeled: false,
+    },
+    Counter {
+        name: "spin_webrtc_leaves_total",
+        help: "Times a peer left a room, including evictions.",
+        labeled: false,
+    },
+    Counter {
+        name: "spin_webrtc_evictions_total",
+        help: "Peers evicted from their rooms, by reason.",
+        labeled: true,
+    },
+    Counter {
+        name: "spin_webrtc_delivery_failures_total",
+        help: "Failed attempts to deliver a message via websocket-bridge, by response status.",
+        labeled: true,
+    },
+    Counter {
+        name: "spin_webrtc_rate_limited_total",
+        help: "Frames rejected for exceeding a rate limit, by scope (connection, peer, or room).",
+        labeled: true,
+    },
+];
+
+const LATENCY: &str = "spin_webrtc_delivery_latency_seconds";
+
+/// Increment the counter series `series`, remembering it so `render` can find it later.
+fn increment(store: &dyn RoomStore, series: &str) -> Result<()> {
+    store.sadd("metrics", series)?;
+    store.incr(&format!("metric:{series}")).map(drop)
+}
+
+pub fn joined(store: &dyn RoomStore) -> Result<()> {
+    increment(store, "spin_webrtc_joins_total")
+}
+
+pub fn left(store: &dyn RoomStore) -> Result<()> {
+    increment(store, "spin_webrtc_leaves_total")
+}
+
+pub fn evicted(store: &dyn RoomStore, reason: &str) -> Result<()> {
+    increment(
+        store,
+        &format!(r#"spin_webrtc_evictions_total{{reason="{reason}"}}"#),
+    )
+}
+
+/// Count a failed delivery attempt; `status` is the HTTP status code, or "error" if there was no response.
+pub fn delivery_failed(store: &dyn RoomStore, status: &str) -> Result<()> {
+    increment(
+        store,
+        &format!(r#"spin_webrtc_delivery_failures_total{{status="{status}"}}"#),
+    )
+}
+
+pub fn rate_limited(store: &dyn RoomStore, scope: &str) -> Result<()> {
+    increment(
+        store,
+        &format!(r#"spin_webrtc_rate_limited_total{{scope="{scope}"}}"#),
+    )
+}
+
+/// Record how long a single delivery attempt took.
+pub fn delivery_latency(store: &dyn RoomStore, elapsed: Duration) -> Result<()> {
+    let seconds = elapsed.as_secs_f64();
+    let bucket = LATENCY_BUCKETS
+        .iter()
+        .position(|&bound| seconds <= bound)
+        .unwrap_or(LATENCY_BUCKETS.len());
+
+    store.incr(&format!("metric:{LATENCY}:bucket:{bucket}"))?;
+    store
+        .incr_by(
+            &format!("metric:{LATENCY}:sum_us"),
+            elapsed.as_micros() as i64,
+        )
+        .map(drop)
+}
+
+fn get(store: &dyn RoomStore, key: &str) -> Result<i64> {
+    Ok(store
+        .get(&format!("metric:{key}"))?
+        .map(|value| value.parse())
+        .transpose()?
+        .unwrap_or(0))
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn render(store: &dyn RoomStore) -> Result<String> {
+    let mut out = String::new();
+
+    let rooms = store.rooms()?;
+    let mut members = 0;
+    for room in &rooms {
+        members += store.members(room)?.len();
+    }
+
+    writeln!(
+        out,
+        "# HELP spin_webrtc_rooms Rooms with at least one member."
+    )?;
+    writeln!(out, "# TYPE spin_webrtc_rooms gauge")?;
+    writeln!(out, "spin_webrtc_rooms {}", rooms.len())?;
+    writeln!(
+        out,
+        "# HELP spin_webrtc_members Peers which have joined a room."
+    )?;
+    writeln!(out, "# TYPE spin_webrtc_members gauge")?;
+    writeln!(out, "spin_webrtc_members {members}")?;
+
+    let mut series = store.smembers("metrics")?;
+    series.sort();
+
+    for counter in COUNTERS {
+        let name = counter.name;
+
+        writeln!(out, "# HELP {name} {}", counter.help)?;
+        writeln!(out, "# TYPE {name} counter")?;
+
+        if counter.labeled {
+            let prefix = format!("{name}{{");
+
+            for series in series.iter().filter(|series| series.starts_with(&prefix)) {
+                writeln!(out, "{series} {}", get(store, series)?)?;
+            }
+        } else {
+            writeln!(out, "{name} {}", get(store, name)?)?;
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP {LATENCY} Time taken by each attempt to deliver a message via websocket-bridge."
+    )?;
+    writeln!(out, "# TYPE {LATENCY} histogram")?;
+
+    let mut cumulative = 0;
+    for (bucket, bound) in LATENCY_BUCKETS.iter().enumerate() {
+        cumulative += get(store, &format!("{LATENCY}:bucket:{bucket}"))?;
+        writeln!(out, r#"{LATENCY}_bucket{{le="{bound}"}} {cumulative}"#)?;
+    }
+    cumulative += get(
+        store,
+        &format!("{LATENCY}:bucket:{}", LATENCY_BUCKETS.len()),
+    )?;
+    writeln!(out, r#"{LATENCY}_bucket{{le="+Inf"}} {cumulative}"#)?;
+    writeln!(
+        out,
+        "{LATENCY}_sum {}",
+        get(store, &format!("{LATENCY}:sum_us"))? as f64 / 1_000_000.0
+    )?;
+    writeln!(out, "{LATENCY}_count {cumulative}")?;
+
+    Ok(out)
+}
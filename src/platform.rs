@@ -0,0 +1,59 @@
+//! The Spin host interfaces we use besides the room store: application variables and outbound HTTP.
+//!
+//! These are only available inside a Spin component, so everything else reaches them through the `Platform`
+//! installed by `handle`, which native unit tests replace with a fake.  Like the logging context, the installed
+//! platform lives in a thread-local, since Spin instantiates the component afresh for each request anyway.
+
+use {
+    anyhow::Result,
+    spin_sdk::{
+        config,
+        http::{Request, Response},
+        outbound_http::{self, OutboundHttpError},
+    },
+    std::{cell::RefCell, rc::Rc},
+};
+
+pub trait Platform {
+    /// Get the value of the specified Spin application variable.
+    fn variable(&self, name: &str) -> Result<String>;
+
+    /// Send `request`, e.g. to deliver a message to a peer via websocket-bridge.
+    fn send(&self, request: Request) -> Result<Response, OutboundHttpError>;
+}
+
+/// `Platform` backed by the Spin host.
+pub struct Spin;
+
+impl Platform for Spin {
+    fn variable(&self, name: &str) -> Result<String> {
+        Ok(config::get(name)?)
+    }
+
+    fn send(&self, request: Request) -> Result<Response, OutboundHttpError> {
+        outbound_http::send_request(request)
+    }
+}
+
+thread_local! {
+    static PLATFORM: RefCell<Option<Rc<dyn Platform>>> = const { RefCell::new(None) };
+}
+
+/// Use `platform` for the rest of this thread's lifetime.
+pub fn install(platform: Rc<dyn Platform>) {
+    PLATFORM.with(|current| *current.borrow_mut() = Some(platform));
+}
+
+fn current() -> Rc<dyn Platform> {
+    PLATFORM
+        .with(|current| current.borrow().clone())
+        .expect("`platform::install` should be called before anything else")
+}
+
+pub fn variable(name: &str) -> Result<String> {
+    current().variable(name)
+}
+
+pub fn send(request: Request) -> Result<Response, OutboundHttpError> {
+    current().send(request)
+}
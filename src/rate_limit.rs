@@ -0,0 +1,42 @@
+//! Token-bucket rate limiting of frames, per peer (or per connection, until it has an ID) and per room.
+//!
+//! Buckets live in the `RoomStore` so that limits hold across every instance of the component.  Like
+//! `RoomStore::incr_by`, updates aren't atomic, so concurrent frames may occasionally slip through a bucket which
+//! should have been empty; that's close enough for keeping a misbehaving client from flooding a room.
+
+use {
+    crate::{settings::RateLimit, store::RoomStore},
+    anyhow::{anyhow, Result},
+};
+
+/// Take a token from the bucket stored at `key`, returning false if it's empty.
+///
+/// `now` is in milliseconds since the Unix epoch.  Buckets start out full, and a `limit` with a zero `burst`
+/// never runs out.
+pub fn take(store: &dyn RoomStore, key: &str, limit: &RateLimit, now: u64) -> Result<bool> {
+    if limit.burst == 0 {
+        return Ok(true);
+    }
+
+    let burst = f64::from(limit.burst);
+
+    let (tokens, updated) = match store.get(key)? {
+        Some(state) => {
+            let (tokens, updated) = state
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("malformed rate limit bucket: {state}"))?;
+
+            (tokens.parse::<f64>()?, updated.parse::<u64>()?)
+        }
+        None => (burst, now),
+    };
+
+    let elapsed = now.saturating_sub(updated) as f64 / 1000.0;
+    let tokens = (tokens + elapsed * limit.per_second).min(burst);
+    let allowed = tokens >= 1.0;
+    let tokens = if allowed { tokens - 1.0 } else { tokens };
+
+    store.set(key, &format!("{tokens} {now}"))?;
+
+    Ok(allowed)
+}
@@ -0,0 +1,149 @@
+//! Runtime settings, read from Spin application variables (see `[variables]` in spin.toml).
+
+use {
+    crate::{logging::Level, platform},
+    anyhow::{Context, Result},
+    spin_webrtc_protocol::ClientConfig,
+    std::{error::Error, str::FromStr, time::Duration},
+};
+
+/// Get the value of the specified variable.
+pub fn get(name: &str) -> Result<String> {
+    platform::variable(name).with_context(|| format!(r#"unable to get "{name}" variable"#))
+}
+
+/// Get the value of the specified variable, parsed as a `T`.
+pub fn parse<T>(name: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Error + Send + Sync + 'static,
+{
+    get(name)?
+        .parse()
+        .with_context(|| format!(r#"unable to parse "{name}" variable"#))
+}
+
+/// Get the value of the specified variable, interpreted as a comma-separated list.
+pub fn list(name: &str) -> Result<Vec<String>> {
+    Ok(get(name)?
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Bearer token required by the admin API, which is disabled if this is empty.
+pub fn admin_token() -> Result<String> {
+    get("admin_token")
+}
+
+/// Origins (e.g. `https://bridge.example.com:9443`) of the websocket-bridge servers allowed to relay frames to
+/// us, defaulting to the `websocket_bridge_host` clients are told to use.
+pub fn bridge_origins() -> Result<Vec<String>> {
+    let origins = list("bridge_origins")?;
+
+    if origins.is_empty() {
+        Ok(vec![format!("https://{}", get("websocket_bridge_host")?)])
+    } else {
+        Ok(origins)
+    }
+}
+
+/// Secret websocket-bridge must present in the `x-bridge-secret` header, which isn't checked if this is empty.
+pub fn bridge_secret() -> Result<String> {
+    get("bridge_secret")
+}
+
+/// Key used to sign invite tokens, which are disabled if this is empty.
+pub fn invite_secret() -> Result<String> {
+    get("invite_secret")
+}
+
+/// Number of seconds an invite token remains valid after it's minted.
+pub fn invite_ttl() -> Result<u64> {
+    parse("invite_ttl")
+}
+
+/// Maximum number of members a room may have, or zero for no limit.
+pub fn max_room_size() -> Result<usize> {
+    parse("max_room_size")
+}
+
+/// Least severe level of log events to write.
+pub fn log_level() -> Result<Level> {
+    parse("log_level")
+}
+
+/// Number of seconds a peer may go without pinging before it's evicted from its room.
+pub fn presence_ttl() -> Result<u64> {
+    parse("presence_ttl")
+}
+
+/// How we handle failures to deliver messages to peers via websocket-bridge.
+pub struct DeliveryPolicy {
+    /// Maximum number of times to try delivering a message in the face of transient failures.
+    pub attempts: u32,
+    /// How long to wait before the first retry; the delay doubles before each subsequent one.
+    pub retry_delay: Duration,
+    /// Number of consecutive messages a peer may fail to receive before it's evicted from its room.
+    pub max_failures: u32,
+}
+
+pub fn delivery_policy() -> Result<DeliveryPolicy> {
+    Ok(DeliveryPolicy {
+        attempts: parse("delivery_attempts")?,
+        retry_delay: Duration::from_millis(parse("delivery_retry_delay_ms")?),
+        max_failures: parse("max_delivery_failures")?,
+    })
+}
+
+/// A token bucket which holds up to `burst` frames and refills at `per_second` frames per second.
+pub struct RateLimit {
+    /// Bucket capacity; zero disables the limit.
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Whether newcomers to occupied rooms must wait in a lobby until the room's host lets them in.
+pub fn lobby() -> Result<bool> {
+    parse("lobby")
+}
+
+/// Limit on the frames each peer may send.
+pub fn peer_rate_limit() -> Result<RateLimit> {
+    Ok(RateLimit {
+        burst: parse("peer_rate_burst")?,
+        per_second: parse("peer_rate_per_second")?,
+    })
+}
+
+/// Limit on the room joins and signals sent to each room, across all of its members.
+pub fn room_rate_limit() -> Result<RateLimit> {
+    Ok(RateLimit {
+        burst: parse("room_rate_burst")?,
+        per_second: parse("room_rate_per_second")?,
+    })
+}
+
+/// Limit on the password attempts made against each room, since each one costs a PBKDF2 derivation.
+pub fn password_rate_limit() -> Result<RateLimit> {
+    Ok(RateLimit {
+        burst: parse("password_rate_burst")?,
+        per_second: parse("password_rate_per_second")?,
+    })
+}
+
+/// Whether to kick and disconnect peers which exceed their rate limit, rather than just dropping the frame.
+pub fn rate_limit_disconnect() -> Result<bool> {
+    parse("rate_limit_disconnect")
+}
+
+pub fn client_config() -> Result<ClientConfig> {
+    Ok(ClientConfig {
+        websocket_bridge_host: get("websocket_bridge_host")?,
+        ice_servers: list("ice_servers")?,
+        features: list("features")?,
+        login_url: Some(get("login_url")?).filter(|url| !url.is_empty()),
+    })
+}
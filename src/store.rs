@@ -0,0 +1,668 @@
+use {
+    anyhow::{Error, Result},
+    spin_sdk::{key_value, redis},
+    std::{cell::RefCell, rc::Rc},
+    thiserror::Error,
+};
+
+#[cfg(test)]
+use std::collections::{HashMap, HashSet};
+
+/// Error reported by a `RoomStore` backend, meaning the store is unreachable or misbehaving.
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("redis error: {0:?}")]
+    Redis(redis::Error),
+
+    #[error("key-value store error: {0:?}")]
+    KeyValue(key_value::Error),
+}
+
+/// Persistent state shared by all requests: which peer IDs belong to which websocket-bridge URLs and which rooms
+/// those peers have joined.
+///
+/// Backends need only implement the Redis-like primitives; the higher-level operations are defined in terms of
+/// them so that every backend uses the same key layout.
+pub trait RoomStore {
+    /// Get the value stored at `key`, if any.
+    fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Store `value` at `key`, replacing any previous value.
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Remove the values and/or sets stored at each of `keys`.
+    fn del(&self, keys: &[&str]) -> Result<()>;
+
+    /// Add `member` to the set stored at `key`.
+    fn sadd(&self, key: &str, member: &str) -> Result<()>;
+
+    /// Remove `member` from the set stored at `key`.
+    fn srem(&self, key: &str, member: &str) -> Result<()>;
+
+    /// Get the members of the set stored at `key`.
+    fn smembers(&self, key: &str) -> Result<Vec<String>>;
+
+    /// Increment the integer stored at `key` (treating a missing value as zero), returning the new value.
+    ///
+    /// This default implementation isn't atomic, so concurrent increments may be lost; backends which can do
+    /// better should override it.
+    fn incr(&self, key: &str) -> Result<i64> {
+        self.incr_by(key, 1)
+    }
+
+    /// Add `delta` to the integer stored at `key` (treating a missing value as zero), returning the new value.
+    ///
+    /// This is never atomic, since not all backends support it, so it's only suitable for statistics which can
+    /// tolerate the occasional lost update.
+    fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        let value = self.get(key)?.map(|n| n.parse()).transpose()?.unwrap_or(0) + delta;
+
+        self.set(key, &value.to_string())?;
+
+        Ok(value)
+    }
+
+    /// Get the ID of the peer connected via the specified bridge URL, if any.
+    fn peer(&self, url: &str) -> Result<Option<String>> {
+        self.get(&format!("id:{url}"))
+    }
+
+    /// Get the bridge URL of the specified peer, if it's connected.
+    fn url(&self, peer: &str) -> Result<Option<String>> {
+        self.get(&format!("url:{peer}"))
+    }
+
+    /// Record that `peer` is connected via `url` and speaks the specified protocol version and features.
+    ///
+    /// If `peer` was previously connected via a different URL (i.e. it has reconnected), the old URL is
+    /// forgotten.
+    fn connect(&self, url: &str, peer: &str, version: u32, features: &[String]) -> Result<()> {
+        if let Some(old_url) = self.url(peer)? {
+            if old_url != url {
+                self.del(&[&format!("id:{old_url}")])?;
+            }
+        }
+
+        self.set(&format!("id:{url}"), peer)?;
+        self.set(&format!("url:{peer}"), url)?;
+        self.set(&format!("version:{peer}"), &version.to_string())?;
+        self.set(&format!("features:{peer}"), &features.join(","))
+    }
+
+    /// Determine whether `peer` negotiated the specified protocol feature.
+    fn supports(&self, peer: &str, feature: &str) -> Result<bool> {
+        Ok(self
+            .get(&format!("features:{peer}"))?
+            .unwrap_or_default()
+            .split(',')
+            .any(|f| f == feature))
+    }
+
+    /// Get the secret which lets a reconnecting client resume its identity as `peer`.
+    fn secret(&self, peer: &str) -> Result<Option<String>> {
+        self.get(&format!("secret:{peer}"))
+    }
+
+    fn set_secret(&self, peer: &str, secret: &str) -> Result<()> {
+        self.set(&format!("secret:{peer}"), secret)
+    }
+
+    /// Record that we failed to deliver a message to `peer`, returning the number of consecutive failures.
+    fn record_failure(&self, peer: &str) -> Result<u32> {
+        let key = format!("failures:{peer}");
+        let failures = self.get(&key)?.map(|n| n.parse()).transpose()?.unwrap_or(0) + 1;
+
+        self.set(&key, &failures.to_string())?;
+
+        Ok(failures)
+    }
+
+    /// Record that we delivered a message to `peer`, resetting its count of consecutive failures.
+    fn record_success(&self, peer: &str) -> Result<()> {
+        let key = format!("failures:{peer}");
+
+        // Check first so the common case costs a read rather than a write.
+        if matches!(self.get(&key)?.as_deref(), Some(failures) if failures != "0") {
+            self.set(&key, "0")?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the identity of the user `peer` authenticated as, if any.
+    fn user(&self, peer: &str) -> Result<Option<String>> {
+        self.get(&format!("user:{peer}"))
+    }
+
+    fn set_user(&self, peer: &str, user: &str) -> Result<()> {
+        self.set(&format!("user:{peer}"), user)
+    }
+
+    /// Forget the connection via `url` (and that `peer`, if any, is connected via it), e.g. because
+    /// websocket-bridge told us that connection closed.
+    ///
+    /// Everything else we know about `peer` is kept so that it can resume its identity by reconnecting; see
+    /// `forget`.
+    fn disconnect(&self, url: &str, peer: Option<&str>) -> Result<()> {
+        self.del(&[&format!("id:{url}"), &format!("rate:url:{url}")])?;
+
+        if let Some(peer) = peer {
+            // The peer may already have reconnected via another URL.
+            if self.url(peer)?.as_deref() == Some(url) {
+                self.del(&[&format!("url:{peer}")])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget everything `connect`, `set_secret`, `set_user`, `touch`, and `record_failure` recorded about `peer`.
+    fn forget(&self, peer: &str) -> Result<()> {
+        if let Some(url) = self.url(peer)? {
+            self.del(&[&format!("id:{url}"), &format!("rate:url:{url}")])?;
+        }
+
+        self.del(&[
+            &format!("url:{peer}"),
+            &format!("version:{peer}"),
+            &format!("features:{peer}"),
+            &format!("secret:{peer}"),
+            &format!("user:{peer}"),
+            &format!("seen:{peer}"),
+            &format!("failures:{peer}"),
+            &format!("rate:peer:{peer}"),
+        ])
+    }
+
+    /// Record that we heard from `peer` at `now` (in seconds since the Unix epoch).
+    fn touch(&self, peer: &str, now: u64) -> Result<()> {
+        self.set(&format!("seen:{peer}"), &now.to_string())
+    }
+
+    /// Get the time (in seconds since the Unix epoch) we last heard from `peer`, if ever.
+    fn last_seen(&self, peer: &str) -> Result<Option<u64>> {
+        Ok(self
+            .get(&format!("seen:{peer}"))?
+            .map(|seen| seen.parse())
+            .transpose()?)
+    }
+
+    /// Add `peer` to `room`.
+    fn join(&self, room: &str, peer: &str) -> Result<()> {
+        self.sadd("rooms", room)?;
+        self.sadd(&format!("room:{room}"), peer)?;
+        self.set(&format!("member:{peer}"), room)
+    }
+
+    /// Remove `peer` from whatever room it has joined, returning the name of that room, if any.
+    fn leave(&self, peer: &str) -> Result<Option<String>> {
+        let room = self.room_of(peer)?;
+
+        if let Some(room) = &room {
+            self.del(&[&format!("member:{peer}")])?;
+            self.srem(&format!("room:{room}"), peer)?;
+
+            if self.members(room)?.is_empty() {
+                self.srem("rooms", room)?;
+                self.del(&[
+                    &format!("password:{room}"),
+                    &format!("rate:room:{room}"),
+                    &format!("host:{room}"),
+                    &format!("locked:{room}"),
+                    &format!("invite_only:{room}"),
+                    &format!("banned:{room}"),
+                ])?;
+            }
+        }
+
+        Ok(room)
+    }
+
+    /// Get the host of `room`, i.e. the member allowed to moderate it, if any.
+    fn host(&self, room: &str) -> Result<Option<String>> {
+        self.get(&format!("host:{room}"))
+    }
+
+    fn set_host(&self, room: &str, peer: &str) -> Result<()> {
+        self.set(&format!("host:{room}"), peer)
+    }
+
+    /// Whether `room` is closed to new members.  Rooms unlock when their last member leaves.
+    fn locked(&self, room: &str) -> Result<bool> {
+        Ok(self.get(&format!("locked:{room}"))?.is_some())
+    }
+
+    fn set_locked(&self, room: &str, locked: bool) -> Result<()> {
+        let key = format!("locked:{room}");
+
+        if locked {
+            self.set(&key, "1")
+        } else {
+            self.del(&[&key])
+        }
+    }
+
+    /// Whether `room` only admits peers presenting a valid invite.  This is forgotten when the last member leaves.
+    fn invite_only(&self, room: &str) -> Result<bool> {
+        Ok(self.get(&format!("invite_only:{room}"))?.is_some())
+    }
+
+    fn set_invite_only(&self, room: &str, invite_only: bool) -> Result<()> {
+        let key = format!("invite_only:{room}");
+
+        if invite_only {
+            self.set(&key, "1")
+        } else {
+            self.del(&[&key])
+        }
+    }
+
+    /// Whether `user` has been kicked out of `room`, and thus may not rejoin it.  Bans are forgotten when the last
+    /// member leaves.
+    fn banned(&self, room: &str, user: &str) -> Result<bool> {
+        Ok(self
+            .smembers(&format!("banned:{room}"))?
+            .iter()
+            .any(|banned| banned == user))
+    }
+
+    fn ban(&self, room: &str, user: &str) -> Result<()> {
+        self.sadd(&format!("banned:{room}"), user)
+    }
+
+    /// Put `peer` in the lobby of `room`, where it waits for the room's host to let it in.
+    fn knock(&self, room: &str, peer: &str) -> Result<()> {
+        self.sadd(&format!("lobby:{room}"), peer)?;
+        self.set(&format!("knocking:{peer}"), room)
+    }
+
+    /// Take `peer` out of whatever lobby it's waiting in, returning the name of that lobby's room, if any.
+    fn leave_lobby(&self, peer: &str) -> Result<Option<String>> {
+        let room = self.get(&format!("knocking:{peer}"))?;
+
+        if let Some(room) = &room {
+            self.srem(&format!("lobby:{room}"), peer)?;
+            self.del(&[&format!("knocking:{peer}")])?;
+        }
+
+        Ok(room)
+    }
+
+    /// Get the name of the room whose lobby `peer` is waiting in, if any.
+    fn lobby_of(&self, peer: &str) -> Result<Option<String>> {
+        self.get(&format!("knocking:{peer}"))
+    }
+
+    /// Get the IDs of the peers waiting in the lobby of `room`.
+    fn lobby(&self, room: &str) -> Result<Vec<String>> {
+        self.smembers(&format!("lobby:{room}"))
+    }
+
+    /// Get the hashed password protecting `room`, if any.  This is forgotten when the last member leaves.
+    fn room_password(&self, room: &str) -> Result<Option<String>> {
+        self.get(&format!("password:{room}"))
+    }
+
+    fn set_room_password(&self, room: &str, hashed: &str) -> Result<()> {
+        self.set(&format!("password:{room}"), hashed)
+    }
+
+    /// Get the names of all rooms with at least one member.
+    fn rooms(&self) -> Result<Vec<String>> {
+        self.smembers("rooms")
+    }
+
+    /// Get the IDs of the peers in `room`.
+    fn members(&self, room: &str) -> Result<Vec<String>> {
+        self.smembers(&format!("room:{room}"))
+    }
+
+    /// Get the name of the room `peer` has joined, if any.
+    fn room_of(&self, peer: &str) -> Result<Option<String>> {
+        self.get(&format!("member:{peer}"))
+    }
+}
+
+/// `RoomStore` which only opens its backend when first used, so that requests which never touch the store (e.g.
+/// for static assets) succeed even if it's misconfigured or unreachable.
+pub struct LazyStore<F> {
+    open: F,
+    store: RefCell<Option<Rc<dyn RoomStore>>>,
+}
+
+impl<F: Fn() -> Result<Box<dyn RoomStore>>> LazyStore<F> {
+    pub fn new(open: F) -> Self {
+        Self {
+            open,
+            store: RefCell::new(None),
+        }
+    }
+
+    fn store(&self) -> Result<Rc<dyn RoomStore>> {
+        let mut store = self.store.borrow_mut();
+
+        if let Some(store) = store.as_ref() {
+            return Ok(store.clone());
+        }
+
+        let opened = Rc::<dyn RoomStore>::from((self.open)()?);
+        *store = Some(opened.clone());
+
+        Ok(opened)
+    }
+}
+
+impl<F: Fn() -> Result<Box<dyn RoomStore>>> RoomStore for LazyStore<F> {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        self.store()?.get(key)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.store()?.set(key, value)
+    }
+
+    fn del(&self, keys: &[&str]) -> Result<()> {
+        self.store()?.del(keys)
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        self.store()?.sadd(key, member)
+    }
+
+    fn srem(&self, key: &str, member: &str) -> Result<()> {
+        self.store()?.srem(key, member)
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<String>> {
+        self.store()?.smembers(key)
+    }
+
+    fn incr(&self, key: &str) -> Result<i64> {
+        self.store()?.incr(key)
+    }
+
+    fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        self.store()?.incr_by(key, delta)
+    }
+}
+
+/// `RoomStore` backed by a Redis server.
+pub struct RedisStore {
+    address: String,
+}
+
+impl RedisStore {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+        }
+    }
+}
+
+impl RoomStore for RedisStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = redis::get(&self.address, key).map_err(redis_error)?;
+
+        Ok(if value.is_empty() {
+            None
+        } else {
+            Some(String::from_utf8(value)?)
+        })
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        redis::set(&self.address, key, value.as_bytes()).map_err(redis_error)
+    }
+
+    fn del(&self, keys: &[&str]) -> Result<()> {
+        redis::del(&self.address, keys)
+            .map(drop)
+            .map_err(redis_error)
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        redis::sadd(&self.address, key, &[member])
+            .map(drop)
+            .map_err(redis_error)
+    }
+
+    fn srem(&self, key: &str, member: &str) -> Result<()> {
+        redis::srem(&self.address, key, &[member])
+            .map(drop)
+            .map_err(redis_error)
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<String>> {
+        redis::smembers(&self.address, key).map_err(redis_error)
+    }
+
+    fn incr(&self, key: &str) -> Result<i64> {
+        redis::incr(&self.address, key).map_err(redis_error)
+    }
+}
+
+fn redis_error(error: redis::Error) -> Error {
+    StoreError::Redis(error).into()
+}
+
+/// `RoomStore` backed by a Spin key-value store.
+///
+/// Key-value stores have neither native sets nor atomic read-modify-write operations, so each set member is
+/// stored under a key of its own, which is what decides whether it's a member.  Each set also has an index key
+/// listing its members so that `smembers` needn't scan the store.  Concurrent updates to the same index may
+/// overwrite each other, so `sadd` re-reads the index to make sure its member made it in, and `smembers` skips
+/// any indexed member whose own key is gone.  Only deleting a whole set scans the store's keys, to catch members
+/// whose index entries were lost anyway.
+pub struct KeyValueStore {
+    store: key_value::Store,
+}
+
+/// How many times to try adding a member to (or removing one from) a set's index before giving up.
+const INDEX_ATTEMPTS: usize = 3;
+
+impl KeyValueStore {
+    pub fn open(name: &str) -> Result<Self> {
+        Ok(Self {
+            store: key_value::Store::open(name).map_err(key_value_error)?,
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match self.store.delete(key) {
+            Ok(()) | Err(key_value::Error::NoSuchKey) => Ok(()),
+            Err(e) => Err(key_value_error(e)),
+        }
+    }
+
+    fn exists(&self, key: &str) -> Result<bool> {
+        self.store.exists(key).map_err(key_value_error)
+    }
+
+    /// Get the members listed in the index of the set stored at `key`.
+    fn index(&self, key: &str) -> Result<Vec<String>> {
+        match self.store.get(index_key(key)) {
+            Ok(value) => Ok(serde_json::from_slice(&value)?),
+            Err(key_value::Error::NoSuchKey) => Ok(Vec::new()),
+            Err(e) => Err(key_value_error(e)),
+        }
+    }
+
+    /// Add `member` to (if `present`) or remove it from the index of the set stored at `key`.
+    fn update_index(&self, key: &str, member: &str, present: bool) -> Result<()> {
+        for _ in 0..INDEX_ATTEMPTS {
+            let mut index = self.index(key)?;
+
+            if index.iter().any(|m| m == member) == present {
+                break;
+            }
+
+            if present {
+                index.push(member.to_owned());
+            } else {
+                index.retain(|m| m != member);
+            }
+
+            self.store
+                .set(index_key(key), serde_json::to_vec(&index)?)
+                .map_err(key_value_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn value_key(key: &str) -> String {
+    format!("value:{key}")
+}
+
+fn index_key(key: &str) -> String {
+    format!("index:{key}")
+}
+
+/// Prefix shared by the keys of all members of the set stored at `key`.
+///
+/// The length of `key` is included so that e.g. the members of "a" can't be confused with those of "a:b".
+fn set_prefix(key: &str) -> String {
+    format!("set:{}:{key}:", key.len())
+}
+
+fn member_key(key: &str, member: &str) -> String {
+    format!("{}{member}", set_prefix(key))
+}
+
+impl RoomStore for KeyValueStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.store.get(value_key(key)) {
+            Ok(value) => Ok(Some(String::from_utf8(value)?)),
+            Err(key_value::Error::NoSuchKey) => Ok(None),
+            Err(e) => Err(key_value_error(e)),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.store
+            .set(value_key(key), value)
+            .map_err(key_value_error)
+    }
+
+    fn del(&self, keys: &[&str]) -> Result<()> {
+        let mut prefixes = Vec::new();
+
+        for key in keys {
+            self.delete(&value_key(key))?;
+
+            if self.exists(&index_key(key))? {
+                prefixes.push(set_prefix(key));
+            }
+        }
+
+        if !prefixes.is_empty() {
+            for key in self.store.get_keys().map_err(key_value_error)? {
+                if prefixes.iter().any(|prefix| key.starts_with(prefix)) {
+                    self.delete(&key)?;
+                }
+            }
+        }
+
+        for key in keys {
+            self.delete(&index_key(key))?;
+        }
+
+        Ok(())
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        self.store
+            .set(member_key(key, member), b"")
+            .map_err(key_value_error)?;
+
+        self.update_index(key, member, true)
+    }
+
+    fn srem(&self, key: &str, member: &str) -> Result<()> {
+        self.delete(&member_key(key, member))?;
+
+        self.update_index(key, member, false)
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<String>> {
+        let mut members = Vec::new();
+
+        for member in self.index(key)? {
+            if self.exists(&member_key(key, &member))? {
+                members.push(member);
+            }
+        }
+
+        Ok(members)
+    }
+}
+
+fn key_value_error(error: key_value::Error) -> Error {
+    StoreError::KeyValue(error).into()
+}
+
+/// `RoomStore` which keeps everything in memory for the lifetime of the value, for native unit tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    values: RefCell<HashMap<String, String>>,
+    sets: RefCell<HashMap<String, HashSet<String>>>,
+}
+
+#[cfg(test)]
+impl RoomStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.values
+            .borrow_mut()
+            .insert(key.to_owned(), value.to_owned());
+
+        Ok(())
+    }
+
+    fn del(&self, keys: &[&str]) -> Result<()> {
+        for &key in keys {
+            self.values.borrow_mut().remove(key);
+            self.sets.borrow_mut().remove(key);
+        }
+
+        Ok(())
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        self.sets
+            .borrow_mut()
+            .entry(key.to_owned())
+            .or_default()
+            .insert(member.to_owned());
+
+        Ok(())
+    }
+
+    fn srem(&self, key: &str, member: &str) -> Result<()> {
+        let mut sets = self.sets.borrow_mut();
+
+        if let Some(set) = sets.get_mut(key) {
+            set.remove(member);
+
+            if set.is_empty() {
+                sets.remove(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .sets
+            .borrow()
+            .get(key)
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,1321 @@
+//! Native tests of `route`, using a `MemoryStore` and a fake `Platform` which records what we send to peers.
+
+use {
+    crate::{
+        credentials,
+        error::ServerError,
+        now,
+        platform::{self, Platform},
+        route, send_to_all,
+        store::{LazyStore, MemoryStore, RoomStore},
+        FEATURES,
+    },
+    anyhow::{anyhow, Result},
+    serde_json::Value,
+    spin_sdk::{
+        http::{Request, Response},
+        outbound_http::OutboundHttpError,
+    },
+    spin_webrtc_protocol::{ClientMessage, Resume, ServerMessage, PROTOCOL_VERSION},
+    std::{cell::RefCell, collections::HashMap, rc::Rc},
+};
+
+/// How the fake websocket-bridge answers deliveries to a given URL.
+#[derive(Copy, Clone)]
+enum Reply {
+    Status(u16),
+    /// The bridge couldn't be reached at all.
+    Refused,
+}
+
+#[derive(Default)]
+struct FakePlatform {
+    variables: RefCell<HashMap<&'static str, String>>,
+    replies: RefCell<HashMap<String, Reply>>,
+    /// Bridge URL and body of every message we tried to deliver, in order.
+    sent: RefCell<Vec<(String, Value)>>,
+}
+
+impl Platform for FakePlatform {
+    fn variable(&self, name: &str) -> Result<String> {
+        self.variables
+            .borrow()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no such variable: {name}"))
+    }
+
+    fn send(&self, request: Request) -> Result<Response, OutboundHttpError> {
+        let url = request.uri().to_string();
+        let body = serde_json::from_slice(request.body().as_deref().unwrap_or_default())
+            .expect("messages to peers should be JSON");
+
+        self.sent.borrow_mut().push((url.clone(), body));
+
+        match self.replies.borrow().get(&url) {
+            Some(Reply::Status(status)) => Ok(http::Response::builder()
+                .status(*status)
+                .body(None)
+                .unwrap()),
+            Some(Reply::Refused) => Err(OutboundHttpError::RequestError),
+            None => Ok(http::Response::builder().body(None).unwrap()),
+        }
+    }
+}
+
+/// `RoomStore` which fails any operation on `key`, but otherwise defers to `inner`.
+struct FailingStore<'a> {
+    inner: &'a MemoryStore,
+    key: String,
+}
+
+impl FailingStore<'_> {
+    fn check(&self, key: &str) -> Result<()> {
+        if key == self.key {
+            Err(anyhow!("injected failure for {key}"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl RoomStore for FailingStore<'_> {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        self.check(key)?;
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.check(key)?;
+        self.inner.set(key, value)
+    }
+
+    fn del(&self, keys: &[&str]) -> Result<()> {
+        for key in keys {
+            self.check(key)?;
+        }
+        self.inner.del(keys)
+    }
+
+    fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        self.check(key)?;
+        self.inner.sadd(key, member)
+    }
+
+    fn srem(&self, key: &str, member: &str) -> Result<()> {
+        self.check(key)?;
+        self.inner.srem(key, member)
+    }
+
+    fn smembers(&self, key: &str) -> Result<Vec<String>> {
+        self.check(key)?;
+        self.inner.smembers(key)
+    }
+}
+
+/// A fresh store and platform, configured with the defaults from spin.toml except that failed deliveries are
+/// retried without delay.
+struct Harness {
+    store: MemoryStore,
+    platform: Rc<FakePlatform>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let platform = Rc::new(FakePlatform::default());
+
+        platform.variables.borrow_mut().extend(
+            [
+                ("room_store", "memory"),
+                ("websocket_bridge_host", "bridge.test"),
+                ("bridge_origins", ""),
+                ("bridge_secret", ""),
+                ("ice_servers", ""),
+                ("features", "chat"),
+                ("presence_ttl", "90"),
+                ("delivery_attempts", "3"),
+                ("delivery_retry_delay_ms", "0"),
+                ("max_delivery_failures", "3"),
+                ("log_level", "error"),
+                ("admin_token", ""),
+                ("max_room_size", "6"),
+                ("peer_rate_burst", "100"),
+                ("peer_rate_per_second", "20"),
+                ("room_rate_burst", "300"),
+                ("room_rate_per_second", "50"),
+                ("password_rate_burst", "10"),
+                ("password_rate_per_second", "0.1"),
+                ("rate_limit_disconnect", "false"),
+                ("lobby", "false"),
+                ("invite_secret", ""),
+                ("invite_ttl", "86400"),
+                ("jwt_hs256_secret", ""),
+                ("jwt_jwks", ""),
+                ("jwt_issuer", ""),
+                ("jwt_audience", ""),
+                ("login_url", ""),
+            ]
+            .map(|(name, value)| (name, value.to_owned())),
+        );
+
+        platform::install(platform.clone());
+
+        Self {
+            store: MemoryStore::default(),
+            platform,
+        }
+    }
+
+    fn set(&self, name: &'static str, value: &str) {
+        self.platform
+            .variables
+            .borrow_mut()
+            .insert(name, value.to_owned());
+    }
+
+    fn reply(&self, url: &str, reply: Reply) {
+        self.platform
+            .replies
+            .borrow_mut()
+            .insert(url.to_owned(), reply);
+    }
+
+    fn request(&self, path: &str, url: &str, body: Option<Vec<u8>>) -> Result<Response> {
+        route(
+            &self.store,
+            &http::Request::builder()
+                .method("POST")
+                .uri(path)
+                .header("ws-bridge-send", url)
+                .body(body.map(Into::into))?,
+        )
+    }
+
+    /// Call the admin API, presenting `token` (if any) as a bearer token.
+    fn admin(&self, method: &str, path: &str, token: Option<&str>) -> Result<Response> {
+        let mut request = http::Request::builder().method(method).uri(path);
+
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+
+        route(&self.store, &request.body(None)?)
+    }
+
+    fn frame(&self, url: &str, message: &ServerMessage) -> Result<Response> {
+        self.request("/frame", url, Some(serde_json::to_vec(message)?))
+    }
+
+    fn disconnect(&self, url: &str) -> Result<Response> {
+        self.request("/disconnect", url, None)
+    }
+
+    /// Connect a client via `url`, returning the ID the server assigns it.
+    fn hello(&self, url: &str) -> String {
+        self.frame(
+            url,
+            &ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+                resume: None,
+            },
+        )
+        .unwrap();
+
+        self.store
+            .peer(url)
+            .unwrap()
+            .expect("peer should have an ID")
+    }
+
+    /// Ask the server to put the client connected via `url` in the room identified by `room_id`.
+    fn enter(&self, url: &str, room_id: &str) {
+        self.frame(
+            url,
+            &ServerMessage::Room {
+                name: room_id,
+                password: None,
+                invite: None,
+                token: None,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Connect a client via `url` and have it join the room identified by `room_id`, returning its ID.
+    fn join(&self, url: &str, room_id: &str) -> String {
+        let id = self.hello(url);
+        self.enter(url, room_id);
+        id
+    }
+
+    /// Take the messages delivered (or at least attempted) to `url` so far.
+    fn sent_to(&self, url: &str) -> Vec<Value> {
+        let mut sent = self.platform.sent.borrow_mut();
+        let (mine, others) = sent.drain(..).partition(|(to, _)| to == url);
+
+        *sent = others;
+
+        mine.into_iter().map(|(_, message)| message).collect()
+    }
+
+    /// Forget every message delivered so far.
+    fn clear(&self) {
+        self.platform.sent.borrow_mut().clear();
+    }
+
+    fn members(&self, room_id: &str) -> Vec<String> {
+        let mut members = self.store.members(&format!("room/{room_id}")).unwrap();
+        members.sort();
+        members
+    }
+}
+
+fn message(kind: &str, id: &str) -> Value {
+    serde_json::json!({ "type": kind, "id": id })
+}
+
+fn types(messages: &[Value]) -> Vec<&str> {
+    messages
+        .iter()
+        .map(|message| message["type"].as_str().unwrap())
+        .collect()
+}
+
+const A: &str = "https://bridge.test/send/a";
+const B: &str = "https://bridge.test/send/b";
+const C: &str = "https://bridge.test/send/c";
+const D: &str = "https://bridge.test/send/d";
+
+#[test]
+fn hello_assigns_id_and_welcomes() {
+    let harness = Harness::new();
+
+    harness.hello(A);
+
+    assert_eq!(types(&harness.sent_to(A)), ["welcome"]);
+}
+
+#[test]
+fn hello_with_unsupported_version_is_rejected() {
+    let harness = Harness::new();
+
+    harness
+        .frame(
+            A,
+            &ServerMessage::Hello {
+                version: 0,
+                features: Vec::new(),
+                resume: None,
+            },
+        )
+        .unwrap();
+
+    assert_eq!(types(&harness.sent_to(A)), ["rejected"]);
+    assert_eq!(harness.store.peer(A).unwrap(), None);
+}
+
+#[test]
+fn frame_from_untrusted_bridge_is_forbidden() {
+    let harness = Harness::new();
+
+    let error = harness
+        .frame("https://evil.test/send/a", &ServerMessage::Ping)
+        .unwrap_err();
+
+    assert!(matches!(ServerError::from(error), ServerError::Forbidden));
+}
+
+#[test]
+fn join_adds_member_and_notifies_room() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    harness.clear();
+
+    let b = harness.join(B, "abc");
+
+    let mut expected = vec![a.clone(), b.clone()];
+    expected.sort();
+
+    assert_eq!(harness.members("abc"), expected);
+    assert_eq!(
+        harness.store.room_of(&b).unwrap().as_deref(),
+        Some("room/abc")
+    );
+    assert_eq!(harness.sent_to(A), [message("add", &b)]);
+    assert_eq!(types(&harness.sent_to(B)), ["welcome", "you", "host"]);
+}
+
+#[test]
+fn rejoining_same_room_notifies_nobody() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.clear();
+
+    harness.enter(B, "abc");
+
+    assert!(harness.members("abc").contains(&b));
+    assert!(harness.sent_to(A).is_empty());
+
+    // B is merely reminded who's already here.
+    let sent = harness.sent_to(B);
+    assert!(sent.contains(&message("add", &a)));
+    assert!(!types(&sent).contains(&"remove"));
+}
+
+#[test]
+fn switching_rooms_leaves_old_room() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let c = harness.join(C, "xyz");
+    harness.clear();
+
+    harness.enter(B, "xyz");
+
+    let mut expected = vec![b.clone(), c];
+    expected.sort();
+
+    assert_eq!(harness.members("abc"), std::slice::from_ref(&a));
+    assert_eq!(harness.members("xyz"), expected);
+    assert_eq!(
+        harness.store.room_of(&b).unwrap().as_deref(),
+        Some("room/xyz")
+    );
+    assert_eq!(harness.sent_to(A), [message("remove", &b)]);
+    assert_eq!(harness.sent_to(C), [message("add", &b)]);
+    assert!(harness.sent_to(B).contains(&message("remove", &a)));
+}
+
+#[test]
+fn join_with_invalid_room_id_is_ignored() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "no spaces allowed");
+
+    assert_eq!(harness.store.room_of(&a).unwrap(), None);
+    assert_eq!(types(&harness.sent_to(A)), ["welcome"]);
+}
+
+#[test]
+fn disconnect_keeps_member_until_presence_ttl() {
+    let harness = Harness::new();
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.clear();
+
+    harness.disconnect(B).unwrap();
+
+    assert!(harness.members("abc").contains(&b));
+    assert_eq!(harness.store.peer(B).unwrap(), None);
+    assert!(harness.sent_to(A).is_empty());
+
+    // Pretend the grace period has passed; the next ping from a roommate sweeps the room.
+    harness.store.touch(&b, 0).unwrap();
+    harness.frame(A, &ServerMessage::Ping).unwrap();
+
+    assert_eq!(harness.members("abc").len(), 1);
+    assert_eq!(harness.store.room_of(&b).unwrap(), None);
+    assert_eq!(harness.store.secret(&b).unwrap(), None);
+    assert!(harness.sent_to(A).contains(&message("remove", &b)));
+}
+
+#[test]
+fn disconnected_member_can_resume() {
+    let harness = Harness::new();
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let secret = harness.store.secret(&b).unwrap().unwrap();
+    harness.disconnect(B).unwrap();
+    harness.clear();
+
+    harness
+        .frame(
+            C,
+            &ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+                resume: Some(Resume {
+                    id: &b,
+                    secret: &secret,
+                }),
+            },
+        )
+        .unwrap();
+    harness.enter(C, "abc");
+
+    assert_eq!(harness.store.peer(C).unwrap().as_deref(), Some(b.as_str()));
+    assert!(harness.members("abc").contains(&b));
+    assert!(harness.sent_to(A).is_empty());
+}
+
+#[test]
+fn resumed_member_catches_up_on_room() {
+    let harness = Harness::new();
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let secret = harness.store.secret(&b).unwrap().unwrap();
+    harness.disconnect(B).unwrap();
+
+    // While B is away, A leaves and C arrives, and neither is delivered to B.
+    harness.enter(A, "xyz");
+    let c = harness.join(C, "abc");
+    harness.clear();
+
+    harness
+        .frame(
+            D,
+            &ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+                resume: Some(Resume {
+                    id: &b,
+                    secret: &secret,
+                }),
+            },
+        )
+        .unwrap();
+    harness.enter(D, "abc");
+
+    let sent = harness.sent_to(D);
+    assert!(sent.contains(&message("add", &c)));
+    assert!(sent.contains(&serde_json::json!({ "type": "members", "ids": [c] })));
+    assert!(harness.sent_to(C).is_empty());
+}
+
+#[test]
+fn disconnect_outside_room_forgets_peer() {
+    let harness = Harness::new();
+
+    let a = harness.hello(A);
+    harness.disconnect(A).unwrap();
+
+    assert_eq!(harness.store.peer(A).unwrap(), None);
+    assert_eq!(harness.store.secret(&a).unwrap(), None);
+}
+
+#[test]
+fn member_unknown_to_bridge_is_removed() {
+    let harness = Harness::new();
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.reply(B, Reply::Status(404));
+    harness.clear();
+
+    let c = harness.join(C, "abc");
+
+    assert!(!harness.members("abc").contains(&b));
+    assert_eq!(harness.store.peer(B).unwrap(), None);
+    assert_eq!(
+        harness.sent_to(A),
+        [message("add", &c), message("remove", &b)]
+    );
+}
+
+#[test]
+fn full_room_turns_newcomers_away() {
+    let harness = Harness::new();
+    harness.set("max_room_size", "2");
+
+    harness.join(A, "abc");
+    harness.join(B, "abc");
+    harness.clear();
+
+    let c = harness.join(C, "abc");
+
+    assert_eq!(harness.members("abc").len(), 2);
+    assert_eq!(harness.store.room_of(&c).unwrap(), None);
+    assert_eq!(types(&harness.sent_to(C)), ["welcome", "you", "room_full"]);
+    assert!(harness.sent_to(A).is_empty());
+}
+
+#[test]
+fn sweep_requires_admin_token() {
+    let harness = Harness::new();
+    harness.set("admin_token", "secret");
+
+    let error = harness.request("/sweep", A, None).unwrap_err();
+
+    assert!(matches!(
+        ServerError::from(error),
+        ServerError::Unauthorized
+    ));
+}
+
+#[test]
+fn send_to_all_evicts_gone_members() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let c = harness.join(C, "abc");
+    harness.join(D, "abc");
+    harness.reply(B, Reply::Status(404));
+    harness.reply(C, Reply::Status(410));
+    harness.clear();
+
+    let summary = send_to_all(
+        &harness.store,
+        "room/abc",
+        Some(&a),
+        None,
+        &ClientMessage::Pong,
+    )
+    .unwrap();
+
+    let mut evicted = summary.evicted;
+    evicted.sort();
+    let mut expected = vec![b.clone(), c.clone()];
+    expected.sort();
+
+    assert_eq!(summary.delivered, 1);
+    assert_eq!(evicted, expected);
+    assert!(summary.failed.is_empty());
+    assert_eq!(harness.members("abc").len(), 2);
+    assert_eq!(harness.store.peer(B).unwrap(), None);
+    assert_eq!(harness.store.peer(C).unwrap(), None);
+}
+
+#[test]
+fn send_to_all_records_failures_and_carries_on() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let c = harness.join(C, "abc");
+    harness.join(D, "abc");
+    harness.reply(B, Reply::Status(503));
+    harness.reply(C, Reply::Refused);
+    harness.clear();
+
+    let summary = send_to_all(
+        &harness.store,
+        "room/abc",
+        Some(&a),
+        None,
+        &ClientMessage::Pong,
+    )
+    .unwrap();
+
+    let mut failed = summary
+        .failed
+        .iter()
+        .map(|(member, _)| member.clone())
+        .collect::<Vec<_>>();
+    failed.sort();
+    let mut expected = vec![b, c];
+    expected.sort();
+
+    assert_eq!(summary.delivered, 1);
+    assert!(summary.evicted.is_empty());
+    assert_eq!(failed, expected);
+    assert_eq!(harness.members("abc").len(), 4);
+    // Broadcasts aren't retried, lest one struggling member hold up the rest.
+    assert_eq!(harness.sent_to(B).len(), 1);
+    assert_eq!(harness.sent_to(C).len(), 1);
+    assert_eq!(types(&harness.sent_to(D)), ["pong"]);
+}
+
+#[test]
+fn direct_delivery_is_retried() {
+    let harness = Harness::new();
+    harness.reply(A, Reply::Status(503));
+
+    harness
+        .frame(
+            A,
+            &ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                features: Vec::new(),
+                resume: None,
+            },
+        )
+        .unwrap_err();
+
+    // Transient failures are retried `delivery_attempts` times.
+    assert_eq!(types(&harness.sent_to(A)), ["welcome"; 3]);
+}
+
+#[test]
+fn send_to_all_evicts_repeatedly_undeliverable_members() {
+    let harness = Harness::new();
+    harness.set("max_delivery_failures", "1");
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.reply(B, Reply::Status(503));
+
+    let summary = send_to_all(
+        &harness.store,
+        "room/abc",
+        Some(&a),
+        None,
+        &ClientMessage::Pong,
+    )
+    .unwrap();
+
+    assert_eq!(summary.delivered, 0);
+    assert_eq!(summary.evicted, std::slice::from_ref(&b));
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, b);
+    assert_eq!(harness.members("abc"), [a]);
+}
+
+#[test]
+fn send_to_all_reports_failed_eviction() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.join(C, "abc");
+    harness.reply(B, Reply::Status(404));
+
+    let store = FailingStore {
+        inner: &harness.store,
+        key: format!("member:{b}"),
+    };
+
+    let summary = send_to_all(&store, "room/abc", Some(&a), None, &ClientMessage::Pong).unwrap();
+
+    assert_eq!(summary.delivered, 1);
+    assert!(summary.evicted.is_empty());
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, b);
+}
+
+#[test]
+fn metric_failure_does_not_fail_delivery() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    harness.join(B, "abc");
+    harness.clear();
+
+    let store = FailingStore {
+        inner: &harness.store,
+        key: "metric:spin_webrtc_delivery_latency_seconds:sum_us".into(),
+    };
+
+    let summary = send_to_all(&store, "room/abc", Some(&a), None, &ClientMessage::Pong).unwrap();
+
+    assert_eq!(summary.delivered, 1);
+    assert!(summary.failed.is_empty());
+    assert_eq!(types(&harness.sent_to(B)), ["pong"]);
+}
+
+#[test]
+fn metric_failure_does_not_fail_join() {
+    let harness = Harness::new();
+
+    let a = harness.hello(A);
+
+    let store = FailingStore {
+        inner: &harness.store,
+        key: "metric:spin_webrtc_joins_total".into(),
+    };
+
+    route(
+        &store,
+        &http::Request::builder()
+            .method("POST")
+            .uri("/frame")
+            .header("ws-bridge-send", A)
+            .body(Some(
+                serde_json::to_vec(&ServerMessage::Room {
+                    name: "abc",
+                    password: None,
+                    invite: None,
+                    token: None,
+                })
+                .unwrap()
+                .into(),
+            ))
+            .unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(harness.members("abc"), [a]);
+}
+
+#[test]
+fn escaped_password_is_accepted() {
+    let harness = Harness::new();
+
+    harness.hello(A);
+    harness
+        .request(
+            "/frame",
+            A,
+            Some(br#"{"type":"room","name":"abc","password":"say \"hi\""}"#.to_vec()),
+        )
+        .unwrap();
+
+    assert_eq!(harness.members("abc").len(), 1);
+    assert!(harness.store.room_password("room/abc").unwrap().is_some());
+}
+
+#[test]
+fn invite_only_room_requires_invite() {
+    let harness = Harness::new();
+    harness.set("invite_secret", "secret");
+    harness.set("admin_token", "secret");
+
+    harness.join(A, "abc");
+    harness
+        .admin("PUT", "/admin/rooms/abc/invite_only", Some("secret"))
+        .unwrap();
+    harness.clear();
+
+    let b = harness.join(B, "abc");
+
+    assert!(!harness.members("abc").contains(&b));
+    assert_eq!(
+        types(&harness.sent_to(B)),
+        ["welcome", "you", "invite_required"]
+    );
+
+    let invite = credentials::mint_invite("abc", now() + 60).unwrap();
+    harness
+        .frame(
+            B,
+            &ServerMessage::Room {
+                name: "abc",
+                password: None,
+                invite: Some(invite.into()),
+                token: None,
+            },
+        )
+        .unwrap();
+
+    assert!(harness.members("abc").contains(&b));
+}
+
+#[test]
+fn invite_only_requires_invite_secret() {
+    let harness = Harness::new();
+    harness.set("admin_token", "secret");
+
+    harness.join(A, "abc");
+    let error = harness
+        .admin("PUT", "/admin/rooms/abc/invite_only", Some("secret"))
+        .unwrap_err();
+
+    assert!(matches!(
+        ServerError::from(error),
+        ServerError::BadRequest(_)
+    ));
+    assert!(!harness.store.invite_only("room/abc").unwrap());
+}
+
+#[test]
+fn clients_cannot_make_rooms_invite_only() {
+    let harness = Harness::new();
+    harness.set("invite_secret", "secret");
+
+    // Older clients could ask for this when creating a room, but only admins can mint invites to get back in.
+    harness.hello(A);
+    harness
+        .request(
+            "/frame",
+            A,
+            Some(br#"{"type":"room","name":"abc","invite_only":true}"#.to_vec()),
+        )
+        .unwrap();
+
+    assert_eq!(harness.members("abc").len(), 1);
+    assert!(!harness.store.invite_only("room/abc").unwrap());
+}
+
+#[test]
+fn password_guesses_are_rate_limited() {
+    let harness = Harness::new();
+    harness.set("password_rate_burst", "2");
+    harness.set("password_rate_per_second", "0");
+
+    let with_password = |password: &'static str| ServerMessage::Room {
+        name: "abc",
+        password: Some(password.into()),
+        invite: None,
+        token: None,
+    };
+
+    harness.hello(A);
+    harness.frame(A, &with_password("right")).unwrap();
+
+    let b = harness.hello(B);
+    harness.frame(B, &with_password("wrong")).unwrap();
+    harness.frame(B, &with_password("wrong")).unwrap();
+    let error = harness.frame(B, &with_password("right")).unwrap_err();
+
+    assert!(matches!(
+        ServerError::from(error),
+        ServerError::TooManyRequests
+    ));
+    assert!(!harness.members("abc").contains(&b));
+}
+
+#[test]
+fn outsiders_do_not_use_up_room_rate_limit() {
+    let harness = Harness::new();
+    harness.set("invite_secret", "secret");
+    harness.set("room_rate_burst", "2");
+    harness.set("room_rate_per_second", "0");
+
+    harness.set("admin_token", "secret");
+
+    let with_invite = |invite: Option<String>| ServerMessage::Room {
+        name: "abc",
+        password: None,
+        invite: invite.map(Into::into),
+        token: None,
+    };
+
+    harness.join(A, "abc");
+    harness
+        .admin("PUT", "/admin/rooms/abc/invite_only", Some("secret"))
+        .unwrap();
+
+    harness.hello(B);
+    for _ in 0..5 {
+        harness.frame(B, &with_invite(None)).unwrap();
+    }
+
+    let c = harness.hello(C);
+    let invite = credentials::mint_invite("abc", now() + 60).unwrap();
+    harness.frame(C, &with_invite(Some(invite))).unwrap();
+
+    assert!(harness.members("abc").contains(&c));
+}
+
+#[test]
+fn hello_flood_is_rate_limited() {
+    let harness = Harness::new();
+    harness.set("peer_rate_burst", "2");
+    harness.set("peer_rate_per_second", "0");
+
+    let hello = ServerMessage::Hello {
+        version: 0,
+        features: Vec::new(),
+        resume: None,
+    };
+
+    harness.frame(A, &hello).unwrap();
+    harness.frame(A, &hello).unwrap();
+    let error = harness.frame(A, &hello).unwrap_err();
+
+    assert!(matches!(
+        ServerError::from(error),
+        ServerError::TooManyRequests
+    ));
+}
+
+/// Encode `value` as a JWT header or claims set.
+fn encode(value: &Value) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    URL_SAFE_NO_PAD.encode(value.to_string())
+}
+
+/// Mint an HS256 JWT with the specified claims, signed with `secret`.
+fn sign(secret: &str, claims: &Value) -> String {
+    use {
+        base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+        hmac::{Hmac, Mac},
+        sha2::Sha256,
+    };
+
+    let signed = format!(
+        "{}.{}",
+        encode(&serde_json::json!({ "alg": "HS256", "typ": "JWT" })),
+        encode(claims)
+    );
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(signed.as_bytes());
+
+    format!(
+        "{signed}.{}",
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Mint an HS256 JWT for `subject`, signed with `secret`.
+fn token(secret: &str, subject: &str) -> String {
+    sign(
+        secret,
+        &serde_json::json!({ "sub": subject, "exp": now() + 60 }),
+    )
+}
+
+#[test]
+fn invalid_tokens_are_unauthenticated() {
+    let claims = |changes: Value| {
+        let mut claims = serde_json::json!({
+            "sub": "alice",
+            "exp": now() + 60,
+            "iss": "https://idp.test",
+            "aud": "spin-webrtc",
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(changes.as_object().unwrap().clone());
+        claims
+    };
+    let unchanged = || claims(serde_json::json!({}));
+
+    let cases = [
+        ("valid", sign("secret", &unchanged()), true),
+        (
+            "expired",
+            sign("secret", &claims(serde_json::json!({ "exp": now() - 60 }))),
+            false,
+        ),
+        (
+            "unsigned",
+            format!(
+                "{}.{}.",
+                encode(&serde_json::json!({ "alg": "none", "typ": "JWT" })),
+                encode(&unchanged())
+            ),
+            false,
+        ),
+        ("bad signature", sign("guess", &unchanged()), false),
+        (
+            "wrong issuer",
+            sign(
+                "secret",
+                &claims(serde_json::json!({ "iss": "https://evil.test" })),
+            ),
+            false,
+        ),
+        (
+            "wrong audience",
+            sign("secret", &claims(serde_json::json!({ "aud": "other" }))),
+            false,
+        ),
+        (
+            "other room",
+            sign("secret", &claims(serde_json::json!({ "rooms": ["xyz"] }))),
+            false,
+        ),
+    ];
+
+    for (case, token, valid) in cases {
+        let harness = Harness::new();
+        harness.set("jwt_hs256_secret", "secret");
+        harness.set("jwt_issuer", "https://idp.test");
+        harness.set("jwt_audience", "spin-webrtc");
+
+        let a = harness.hello(A);
+        harness
+            .frame(
+                A,
+                &ServerMessage::Room {
+                    name: "abc",
+                    password: None,
+                    invite: None,
+                    token: Some(token.into()),
+                },
+            )
+            .unwrap();
+
+        let sent = harness.sent_to(A);
+
+        if valid {
+            assert_eq!(harness.members("abc"), [a], "{case}");
+        } else {
+            assert_eq!(types(&sent).last(), Some(&"unauthenticated"), "{case}");
+            assert!(harness.members("abc").is_empty(), "{case}");
+            assert_eq!(harness.store.user(&a).unwrap(), None, "{case}");
+        }
+    }
+}
+
+#[test]
+fn kicked_user_cannot_rejoin() {
+    let harness = Harness::new();
+    harness.set("jwt_hs256_secret", "secret");
+
+    let with_token = |subject| ServerMessage::Room {
+        name: "abc",
+        password: None,
+        invite: None,
+        token: Some(token("secret", subject).into()),
+    };
+
+    let a = harness.hello(A);
+    harness.frame(A, &with_token("alice")).unwrap();
+    let b = harness.hello(B);
+    harness.frame(B, &with_token("bob")).unwrap();
+
+    harness.frame(A, &ServerMessage::Kick { id: &b }).unwrap();
+    harness.clear();
+
+    // Reconnecting under a new ID doesn't help.
+    let c = harness.hello(C);
+    harness.frame(C, &with_token("bob")).unwrap();
+
+    assert_eq!(harness.members("abc"), [a]);
+    assert!(!harness.store.locked("room/abc").unwrap());
+    assert!(harness
+        .sent_to(C)
+        .contains(&serde_json::json!({ "type": "kicked" })));
+    assert_eq!(harness.store.room_of(&c).unwrap(), None);
+}
+
+#[test]
+fn kicking_anonymous_peer_locks_room() {
+    let harness = Harness::new();
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+
+    harness.frame(A, &ServerMessage::Kick { id: &b }).unwrap();
+    harness.clear();
+
+    harness.enter(B, "abc");
+
+    assert_eq!(harness.members("abc"), std::slice::from_ref(&a));
+    assert!(harness.store.locked("room/abc").unwrap());
+    assert!(types(&harness.sent_to(B)).contains(&"room_locked"));
+}
+
+#[test]
+fn rate_limit_kick_does_not_lock_room() {
+    let harness = Harness::new();
+    harness.set("peer_rate_burst", "3");
+    harness.set("peer_rate_per_second", "0");
+    harness.set("rate_limit_disconnect", "true");
+
+    let a = harness.join(A, "abc");
+    harness.join(B, "abc");
+
+    assert!((0..5).any(|_| harness.frame(B, &ServerMessage::Ping).is_err()));
+
+    assert_eq!(harness.members("abc"), [a]);
+    assert!(!harness.store.locked("room/abc").unwrap());
+}
+
+#[test]
+fn admitting_into_full_room_keeps_knocker_waiting() {
+    let harness = Harness::new();
+    harness.set("lobby", "true");
+    harness.set("max_room_size", "2");
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    let c = harness.join(C, "abc");
+    harness.clear();
+
+    // C stays in the lobby, none the wiser, and A is reminded that it's still waiting.
+    harness.frame(A, &ServerMessage::Admit { id: &b }).unwrap();
+    harness.frame(A, &ServerMessage::Admit { id: &c }).unwrap();
+
+    assert_eq!(harness.store.room_of(&b).unwrap(), Some("room/abc".into()));
+    assert_eq!(harness.store.room_of(&c).unwrap(), None);
+    assert_eq!(harness.store.lobby_of(&c).unwrap(), Some("room/abc".into()));
+    assert!(harness.sent_to(C).is_empty());
+
+    let sent = harness.sent_to(A);
+    assert_eq!(types(&sent), ["remove", "add", "knock"]);
+    assert_eq!(sent[0], message("remove", &b));
+    assert_eq!(sent[2]["id"], c.as_str());
+}
+
+#[test]
+fn resumed_host_hears_missed_knocks() {
+    let harness = Harness::new();
+    harness.set("lobby", "true");
+
+    let a = harness.join(A, "abc");
+    let secret = harness.store.secret(&a).unwrap().unwrap();
+    harness.disconnect(A).unwrap();
+
+    let b = harness.join(B, "abc");
+    harness.clear();
+
+    harness
+        .frame(
+            C,
+            &ServerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+                resume: Some(Resume {
+                    id: &a,
+                    secret: &secret,
+                }),
+            },
+        )
+        .unwrap();
+    harness.enter(C, "abc");
+
+    let sent = harness.sent_to(C);
+    assert_eq!(types(&sent).last(), Some(&"knock"));
+    assert_eq!(sent.last().unwrap()["id"], b.as_str());
+}
+
+#[test]
+fn denying_knock_tells_host() {
+    let harness = Harness::new();
+    harness.set("lobby", "true");
+
+    harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.clear();
+
+    harness.frame(A, &ServerMessage::Deny { id: &b }).unwrap();
+
+    assert_eq!(harness.store.lobby_of(&b).unwrap(), None);
+    assert_eq!(harness.sent_to(A), [message("remove", &b)]);
+    assert_eq!(types(&harness.sent_to(B)), ["denied"]);
+}
+
+#[test]
+fn config_does_not_need_store() {
+    let _harness = Harness::new();
+    let store = LazyStore::new(|| Err(anyhow!("store unavailable")));
+    let get = |path| {
+        http::Request::builder()
+            .method("GET")
+            .uri(path)
+            .body(None)
+            .unwrap()
+    };
+
+    assert_eq!(route(&store, &get("/config")).unwrap().status(), 200);
+    assert!(route(&store, &get("/metrics")).is_err());
+}
+
+fn body(response: &Response) -> Value {
+    serde_json::from_slice(response.body().as_deref().unwrap()).unwrap()
+}
+
+#[test]
+fn admin_lists_rooms() {
+    let harness = Harness::new();
+    harness.set("admin_token", "secret");
+
+    harness.join(A, "abc");
+    harness.join(B, "abc");
+    harness.join(C, "xyz");
+
+    let response = harness
+        .admin("GET", "/admin/rooms", Some("secret"))
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        body(&response),
+        serde_json::json!([
+            { "room": "abc", "members": 2 },
+            { "room": "xyz", "members": 1 },
+        ])
+    );
+}
+
+#[test]
+fn admin_shows_room() {
+    let harness = Harness::new();
+    harness.set("admin_token", "secret");
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+
+    let room = body(
+        &harness
+            .admin("GET", "/admin/rooms/abc", Some("secret"))
+            .unwrap(),
+    );
+
+    let mut expected = vec![a.clone(), b];
+    expected.sort();
+
+    assert_eq!(room["room"], "abc");
+    assert_eq!(room["host"], a.as_str());
+    assert_eq!(room["locked"], false);
+    assert_eq!(
+        room["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["id"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    let error = harness
+        .admin("GET", "/admin/rooms/empty", Some("secret"))
+        .unwrap_err();
+
+    assert!(matches!(ServerError::from(error), ServerError::NotFound));
+}
+
+#[test]
+fn admin_kicks_member() {
+    let harness = Harness::new();
+    harness.set("admin_token", "secret");
+
+    let a = harness.join(A, "abc");
+    let b = harness.join(B, "abc");
+    harness.clear();
+
+    let response = harness
+        .admin(
+            "DELETE",
+            &format!("/admin/rooms/abc/members/{b}"),
+            Some("secret"),
+        )
+        .unwrap();
+
+    assert_eq!(response.status(), 204);
+    assert_eq!(harness.members("abc"), [a]);
+    assert_eq!(types(&harness.sent_to(B)), ["remove", "kicked"]);
+    assert_eq!(harness.sent_to(A), [message("remove", &b)]);
+    // Unlike the host, admins don't lock the room on kicking an anonymous peer.
+    assert!(!harness.store.locked("room/abc").unwrap());
+
+    // B is already gone.
+    let error = harness
+        .admin(
+            "DELETE",
+            &format!("/admin/rooms/abc/members/{b}"),
+            Some("secret"),
+        )
+        .unwrap_err();
+
+    assert!(matches!(ServerError::from(error), ServerError::NotFound));
+}
+
+#[test]
+fn admin_api_requires_admin_token() {
+    let harness = Harness::new();
+
+    harness.join(A, "abc");
+
+    // With no `admin_token` configured, the admin API is disabled altogether.
+    let error = harness
+        .admin("GET", "/admin/rooms", Some("secret"))
+        .unwrap_err();
+
+    assert!(matches!(ServerError::from(error), ServerError::Forbidden));
+
+    harness.set("admin_token", "secret");
+
+    for token in [None, Some("wrong")] {
+        let error = harness.admin("GET", "/admin/rooms", token).unwrap_err();
+
+        assert!(matches!(
+            ServerError::from(error),
+            ServerError::Unauthorized
+        ));
+    }
+}